@@ -0,0 +1,108 @@
+//! Feeds arbitrary triangle lists to `try_split_meshlets_with_report` and checks the same invariants as the
+//! `split_invariants_*` tests: every triangle that isn't a sliver ends up in a chunk, chunk indices are in range,
+//! and chunk vertices are source vertices or lie in their chunk's cell.
+//!
+//! Run with `cargo fuzz run split_mesh` from the repository root.
+#![no_main]
+
+use arbitrary::Arbitrary;
+use bevy::prelude::{Mesh, Vec3};
+use bevy::render::{mesh::{Indices, PrimitiveTopology}, render_asset::RenderAssetUsages};
+use libfuzzer_sys::fuzz_target;
+use voyage_abeon::collider_divider::{self, BoundaryMode, SplitError, SplitSettings};
+use voyage_abeon::MAP_LODS;
+
+/// Clipping costs grow with the number of chunks a triangle covers,
+/// so corners farther out than this only make the fuzzer find timeouts
+const MAX_EXTENT: f32 = 256.0;
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    positions: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+    /// Chunk size in eighths of a unit, on top of one unit
+    chunk_size: u8,
+    chunk_height: Option<u8>,
+    clip: bool,
+    lods: bool,
+    heightfields: bool,
+}
+
+impl Input {
+    fn settings(&self) -> SplitSettings {
+        let mut settings = SplitSettings::new(1.0 + self.chunk_size as f32 / 8.0);
+        if let Some(chunk_height) = self.chunk_height {
+            settings = settings.with_chunk_height(1.0 + chunk_height as f32 / 8.0);
+        }
+        if self.clip {
+            settings = settings.with_boundary_mode(BoundaryMode::Clip);
+        }
+        if self.lods {
+            settings = settings.with_lods(MAP_LODS);
+        }
+        if self.heightfields {
+            settings = settings.with_heightfields(0.01);
+        }
+        settings
+    }
+}
+
+/// Whether a triangle is thinner than f32 can resolve next to its longest edge,
+/// so clipping it may leave only pieces without an area
+fn is_sliver([a, b, c]: [Vec3; 3]) -> bool {
+    let longest_edge = a.distance_squared(b).max(b.distance_squared(c)).max(c.distance_squared(a));
+    (b - a).cross(c - a).length() <= longest_edge * f32::EPSILON
+}
+
+fuzz_target!(|input: Input| {
+    if input.positions.iter().flatten().any(|coordinate| coordinate.is_finite() && coordinate.abs() > MAX_EXTENT) {
+        return;
+    }
+
+    let settings = input.settings();
+    let grid = settings.grid();
+    let mut indices = input.indices.clone();
+    indices.truncate(indices.len() - indices.len() % 3);
+
+    let mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default())
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, input.positions.clone())
+        .with_inserted_indices(Indices::U32(indices.clone()));
+
+    let (meshlets, report) = match collider_divider::try_split_meshlets_with_report(&mesh, &settings) {
+        Ok(split) => split,
+        Err(SplitError::IndexOutOfRange { .. }) => return,
+        Err(SplitError::VertexOutOfGrid(index)) => {
+            assert!(!Vec3::from(input.positions[index as usize]).is_finite(), "vertex {index} is in range but was refused");
+            return;
+        },
+        Err(err) => panic!("unexpected error: {err}"),
+    };
+
+    // without a cleanup pass, source triangles are the mesh's triangles
+    let sources: Vec<[Vec3; 3]> = indices.chunks_exact(3)
+        .map(|triangle| [0, 1, 2].map(|i| Vec3::from(input.positions[triangle[i] as usize])))
+        .collect();
+    assert_eq!(report.source_triangles, sources.len());
+    for missing in &report.missing_triangles {
+        assert!(is_sliver(sources[*missing]), "triangle {missing} {:?} is in no chunk", sources[*missing]);
+    }
+    assert_eq!(report.emitted_triangles, meshlets.iter().map(|meshlet| meshlet.indices.len()).sum::<usize>());
+
+    for meshlet in &meshlets {
+        for triangle in &meshlet.indices {
+            assert!(triangle.iter().all(|i| (*i as usize) < meshlet.vertices.len()), "chunk {:?} indexes past its vertices", meshlet.chunk_pos);
+            let corners = triangle.map(|i| meshlet.vertices[i as usize]);
+
+            if !input.clip {
+                assert!(sources.contains(&corners), "chunk {:?} has triangle {corners:?} that isn't in the mesh", meshlet.chunk_pos);
+                continue;
+            }
+
+            let epsilon = MAX_EXTENT * 1e-4;
+            for corner in corners {
+                assert!(corner.cmpge(grid.chunk_min(meshlet.chunk_pos) - epsilon).all() && corner.cmple(grid.chunk_max(meshlet.chunk_pos) + epsilon).all(),
+                    "corner {corner} leaks out of chunk {:?}", meshlet.chunk_pos);
+            }
+        }
+    }
+});
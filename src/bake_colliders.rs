@@ -0,0 +1,293 @@
+//! Splits every mesh of a glTF map into chunk colliders ahead of time, without opening a window.
+//! Like the game, it walks the node hierarchy of the map's default scene, places each primitive with its node's world transform
+//! and leaves out nodes that opted out of the split with `{"split": false}` extras.
+//!
+//! Usage: `bake-colliders <map.glb> [--out <dir>] [--chunk-size <size>] [--chunk-height <height>] [--duplicate] [--cleanup <tolerance> | --no-cleanup] [--heightfields <tolerance> | --no-heightfields] [--report <file>] [--debug-obj <file>]`
+//!
+//! The baked chunks are written in the same format and under the same names as the collider cache
+//! the game reads on startup, so baking into the game's cache directory skips splitting at launch.
+//! `--debug-obj` also exports the grid chunks the split produced, with outlines of the chunk tree leaves the game merges them into.
+
+use bevy::prelude::{GlobalTransform, Mat4, Mesh, Quat, Transform, Vec3};
+use bevy::render::{mesh::{Indices, PrimitiveTopology}, render_asset::RenderAssetUsages};
+use gltf::accessor::{DataType, Dimensions};
+use gltf::mesh::{util::ReadIndices, Mode, Semantic};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use voyage_abeon::{collider_cache, collider_divider::{self, BoundaryMode, ChunkTree, SplitSettings}, collider_surface, map_scene, COLLIDER_CACHE_DIR, MAP_CHUNK_TREE, MAP_SPLIT_SETTINGS};
+
+struct Args {
+    map: PathBuf,
+    out_dir: PathBuf,
+    report: Option<PathBuf>,
+    debug_obj: Option<PathBuf>,
+    settings: SplitSettings,
+}
+
+const USAGE: &str = "usage: bake-colliders <map.glb> [--out <dir>] [--chunk-size <size>] [--chunk-height <height>] [--duplicate] [--cleanup <tolerance> | --no-cleanup] [--heightfields <tolerance> | --no-heightfields] [--report <file>] [--debug-obj <file>]";
+
+fn parse_args() -> Result<Args, String> {
+    let mut map = None;
+    let mut out_dir = PathBuf::from(COLLIDER_CACHE_DIR);
+    let mut report = None;
+    let mut debug_obj = None;
+    // defaults match what `spawn_map` uses, so the game can pick the baked files up as-is
+    let mut settings = MAP_SPLIT_SETTINGS;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" => out_dir = args.next().ok_or("--out needs a directory")?.into(),
+            "--report" => report = Some(args.next().ok_or("--report needs a file")?.into()),
+            "--debug-obj" => debug_obj = Some(args.next().ok_or("--debug-obj needs a file")?.into()),
+            "--chunk-size" => {
+                let chunk_size = args.next().ok_or("--chunk-size needs a value")?;
+                settings.chunk_size = chunk_size.parse().map_err(|_| format!("invalid chunk size {chunk_size}"))?;
+            },
+            "--chunk-height" => {
+                let chunk_height = args.next().ok_or("--chunk-height needs a value")?;
+                settings.chunk_height = Some(chunk_height.parse().map_err(|_| format!("invalid chunk height {chunk_height}"))?);
+            },
+            "--duplicate" => settings.boundary_mode = BoundaryMode::Duplicate,
+            "--cleanup" => {
+                let tolerance = args.next().ok_or("--cleanup needs a tolerance")?;
+                settings.cleanup_tolerance = Some(tolerance.parse().map_err(|_| format!("invalid cleanup tolerance {tolerance}"))?);
+            },
+            "--no-cleanup" => settings.cleanup_tolerance = None,
+            "--heightfields" => {
+                let tolerance = args.next().ok_or("--heightfields needs a tolerance")?;
+                settings.heightfield_tolerance = Some(tolerance.parse().map_err(|_| format!("invalid heightfield tolerance {tolerance}"))?);
+            },
+            "--no-heightfields" => settings.heightfield_tolerance = None,
+            _ if map.is_none() && !arg.starts_with("--") => map = Some(PathBuf::from(&arg)),
+            _ => return Err(format!("unexpected argument {arg}")),
+        }
+    }
+
+    Ok(Args {
+        map: map.ok_or("no map given")?,
+        out_dir,
+        report,
+        debug_obj,
+        settings,
+    })
+}
+
+/// Same mapping as bevy's glTF loader, which refuses to load the whole file if it finds anything else
+fn topology(mode: Mode) -> Result<PrimitiveTopology, String> {
+    match mode {
+        Mode::Points => Ok(PrimitiveTopology::PointList),
+        Mode::Lines => Ok(PrimitiveTopology::LineList),
+        Mode::LineStrip => Ok(PrimitiveTopology::LineStrip),
+        Mode::Triangles => Ok(PrimitiveTopology::TriangleList),
+        Mode::TriangleStrip => Ok(PrimitiveTopology::TriangleStrip),
+        other => Err(format!("primitive mode {other:?} can't be loaded by bevy")),
+    }
+}
+
+/// Same conversion as bevy's glTF loader, so the baked positions match the game's to the last bit
+fn node_transform(node: &gltf::Node) -> Transform {
+    match node.transform() {
+        gltf::scene::Transform::Matrix { matrix } => Transform::from_matrix(Mat4::from_cols_array_2d(&matrix)),
+        gltf::scene::Transform::Decomposed { translation, rotation, scale } => Transform {
+            translation: Vec3::from(translation),
+            rotation: Quat::from_array(rotation),
+            scale: Vec3::from(scale),
+        },
+    }
+}
+
+/// A primitive named `<node>#<primitive>`, or why it can't be loaded
+type LoadedPrimitive = (String, Result<Mesh, String>);
+
+/// Loads the positions, indices and surfaces of every primitive placed by a node that doesn't opt out of the split
+fn load_primitives(path: &Path) -> Result<Vec<LoadedPrimitive>, gltf::Error> {
+    let gltf = gltf::Gltf::open(path)?;
+    // only buffers are needed, so skip decoding the textures
+    let buffers = gltf::import_buffers(&gltf.document, path.parent(), gltf.blob.clone())?;
+
+    // walk down from the roots of the map's scene, as `spawn_map` does
+    let mut primitives = Vec::new();
+    for root in map_scene::scene_roots(&gltf.document).into_iter().filter_map(|index| gltf.document.nodes().nth(index)) {
+        load_node_primitives(&root, GlobalTransform::IDENTITY, &buffers, &mut primitives);
+    }
+
+    Ok(primitives)
+}
+
+fn load_node_primitives(node: &gltf::Node, parent_transform: GlobalTransform, buffers: &[gltf::buffer::Data], primitives: &mut Vec<LoadedPrimitive>) {
+    // the game draws these whole, each with a single trimesh collider it builds at load time
+    if map_scene::opts_out_of_split(node.extras().as_ref().map(|extras| extras.get())) {
+        return;
+    }
+
+    let transform = parent_transform.mul_transform(node_transform(node));
+    let node_name = node.name().map(str::to_string).unwrap_or_else(|| format!("node{}", node.index()));
+
+    if let Some(mesh) = node.mesh() {
+        for primitive in mesh.primitives() {
+            let name = format!("{}#{}", node_name, primitive.index());
+            let mesh = load_primitive(&primitive, buffers).map(|mut mesh| {
+                map_scene::bake_transform(&mut mesh, &transform.affine());
+                mesh
+            });
+            primitives.push((name, mesh));
+        }
+    }
+
+    for child in node.children() {
+        load_node_primitives(&child, transform, buffers, primitives);
+    }
+}
+
+fn load_primitive(primitive: &gltf::Primitive, buffers: &[gltf::buffer::Data]) -> Result<Mesh, String> {
+    let primitive_topology = topology(primitive.mode())?;
+
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+    let mut bevy_mesh = Mesh::new(primitive_topology, RenderAssetUsages::default());
+
+    if let Some(positions) = reader.read_positions() {
+        bevy_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions.collect::<Vec<[f32; 3]>>());
+    }
+    if let Some(indices) = reader.read_indices() {
+        bevy_mesh.insert_indices(match indices {
+            ReadIndices::U8(is) => Indices::U16(is.map(|x| x as u16).collect()),
+            ReadIndices::U16(is) => Indices::U16(is.collect()),
+            ReadIndices::U32(is) => Indices::U32(is.collect()),
+        });
+    }
+    // the game registers `_SURFACE` with bevy's glTF loader, which skips it unless it is stored as u32s
+    let surfaces = primitive.get(&Semantic::Extras("SURFACE".to_string()))
+        .filter(|accessor| accessor.data_type() == DataType::U32 && accessor.dimensions() == Dimensions::Scalar)
+        .and_then(|accessor| gltf::accessor::Iter::<u32>::new(accessor, |buffer| Some(buffers[buffer.index()].0.as_slice())));
+    if let Some(surfaces) = surfaces {
+        bevy_mesh.insert_attribute(collider_surface::ATTRIBUTE_SURFACE, surfaces.collect::<Vec<u32>>());
+    }
+
+    // bevy un-indexes primitives without normals to give them flat normals -
+    // do the same, or the cache keys won't match the meshes the game loads
+    if reader.read_normals().is_none() && primitive_topology == PrimitiveTopology::TriangleList {
+        bevy_mesh.duplicate_vertices();
+    }
+    // same fallback as `spawn_map`
+    collider_surface::insert_material_surface(&mut bevy_mesh, collider_surface::material_surface(primitive.material().index()));
+
+    Ok(bevy_mesh)
+}
+
+fn run(args: &Args) -> Result<bool, String> {
+    let primitives = load_primitives(&args.map).map_err(|err| format!("failed to load {}: {}", args.map.display(), err))?;
+
+    let mut report = String::new();
+    let mut ok = true;
+    let mut total_source = 0;
+    let mut total_emitted = 0;
+    let mut total_duplicated = 0;
+    let mut all_chunk_triangles = Vec::new();
+    let mut heightfield_chunks = 0;
+    let mut all_meshlets = Vec::new();
+
+    writeln!(report, "map: {}", args.map.display()).unwrap();
+    writeln!(report, "chunk size: {}, chunk height: {:?}, boundary mode: {:?}, cleanup tolerance: {:?}, heightfield tolerance: {:?}",
+        args.settings.chunk_size, args.settings.chunk_height, args.settings.boundary_mode, args.settings.cleanup_tolerance, args.settings.heightfield_tolerance).unwrap();
+
+    for (name, mesh) in &primitives {
+        let mesh = match mesh {
+            Ok(mesh) => mesh,
+            Err(err) => {
+                writeln!(report, "\n{name}: ERROR: {err}").unwrap();
+                ok = false;
+                continue;
+            }
+        };
+
+        let (mut meshlets, split_report) = match collider_divider::try_split_meshlets_with_report(mesh, &args.settings) {
+            Ok(split) => split,
+            Err(err) => {
+                writeln!(report, "\n{name}: ERROR: {err}").unwrap();
+                ok = false;
+                continue;
+            }
+        };
+        meshlets.sort_by_key(|meshlet| (meshlet.chunk_pos.x, meshlet.chunk_pos.y, meshlet.chunk_pos.z));
+
+        let key = collider_cache::cache_key(mesh, &args.settings);
+        let path = collider_cache::store(&args.out_dir, key, &meshlets)
+            .map_err(|err| format!("failed to write {}: {}", collider_cache::cache_path(&args.out_dir, key).display(), err))?;
+
+        total_source += split_report.source_triangles;
+        total_emitted += split_report.emitted_triangles;
+        total_duplicated += split_report.duplicated_triangles;
+
+        writeln!(report, "\n{name}: {split_report} -> {}", path.display()).unwrap();
+
+        if !split_report.is_valid() {
+            writeln!(report, "  ERROR: source triangles {:?} ended up in no chunk", split_report.missing_triangles).unwrap();
+            ok = false;
+        }
+
+        for meshlet in &meshlets {
+            let lod_triangles: Vec<String> = meshlet.lods.iter().map(|lod| lod.indices.len().to_string()).collect();
+            let heightfield = match &meshlet.heightfield {
+                Some(heightfield) => {
+                    heightfield_chunks += 1;
+                    format!(", heightfield {0}x{0}", heightfield.resolution())
+                },
+                None => String::new(),
+            };
+            writeln!(report, "  chunk ({}, {}, {}): {} triangles{heightfield}, lods [{}]",
+                meshlet.chunk_pos.x, meshlet.chunk_pos.y, meshlet.chunk_pos.z, meshlet.indices.len(), lod_triangles.join(", ")).unwrap();
+            all_chunk_triangles.push(meshlet.indices.len());
+        }
+
+        if args.debug_obj.is_some() {
+            all_meshlets.extend(meshlets);
+        }
+    }
+
+    writeln!(report, "\ntotal: {} primitives, {} chunks, {total_source} source triangles, {total_emitted} emitted, {total_duplicated} duplicated",
+        primitives.len(), all_chunk_triangles.len()).unwrap();
+    writeln!(report, "heightfield chunks: {heightfield_chunks}").unwrap();
+
+    if let (Some(min), Some(max)) = (all_chunk_triangles.iter().min(), all_chunk_triangles.iter().max()) {
+        writeln!(report, "triangles per chunk: min {min}, mean {:.1}, max {max}",
+            total_emitted as f32 / all_chunk_triangles.len() as f32).unwrap();
+    }
+
+    if let Some(debug_obj) = &args.debug_obj {
+        // the grid chunks as the split left them, with the tree only outlined, so duplicates inside a leaf stay visible
+        let meshlets = collider_divider::merge_meshlets(all_meshlets);
+        let triangle_counts = meshlets.iter().map(|meshlet| (meshlet.chunk_pos, meshlet.indices.len())).collect();
+        let tree = ChunkTree::new(&triangle_counts, &MAP_CHUNK_TREE);
+        collider_divider::export_debug_obj(&meshlets, &args.settings.grid(), &tree, debug_obj)
+            .map_err(|err| format!("failed to write {}: {}", debug_obj.display(), err))?;
+        writeln!(report, "debug export: {} chunks -> {}", meshlets.len(), debug_obj.display()).unwrap();
+    }
+
+    print!("{report}");
+    if let Some(report_path) = &args.report {
+        std::fs::write(report_path, &report).map_err(|err| format!("failed to write {}: {}", report_path.display(), err))?;
+    }
+
+    Ok(ok)
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err}\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match run(&args) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
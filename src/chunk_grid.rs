@@ -0,0 +1,175 @@
+use bevy::prelude::{GlobalTransform, Vec3};
+use crate::collider_divider::ChunkPos;
+
+/// The grid a mesh is split into chunks on.
+/// The grid lives in the mesh's own ("local") space, and `transform` places that space in the world,
+/// so loaders can be mapped to chunks however the owning entity is moved, rotated or scaled.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ChunkGrid {
+    /// Local position of the lowest corner of chunk (0, 0, 0)
+    pub origin: Vec3,
+    /// Edge length of a chunk along x and z
+    pub cell_size: f32,
+    /// Height of a chunk, or `None` if chunks span the whole height of the mesh
+    pub cell_height: Option<f32>,
+    /// Transform of the entity owning the mesh
+    pub transform: GlobalTransform,
+}
+
+impl ChunkGrid {
+    pub const fn new(cell_size: f32) -> Self {
+        Self {
+            origin: Vec3::ZERO,
+            cell_size,
+            cell_height: None,
+            transform: GlobalTransform::IDENTITY,
+        }
+    }
+
+    pub const fn with_origin(mut self, origin: Vec3) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    pub const fn with_cell_height(mut self, cell_height: Option<f32>) -> Self {
+        self.cell_height = cell_height;
+        self
+    }
+
+    pub const fn with_transform(mut self, transform: GlobalTransform) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    pub fn world_to_local(&self, world: Vec3) -> Vec3 {
+        self.transform.affine().inverse().transform_point3(world)
+    }
+
+    pub fn local_to_world(&self, local: Vec3) -> Vec3 {
+        self.transform.transform_point(local)
+    }
+
+    pub fn local_to_chunk(&self, local: Vec3) -> ChunkPos {
+        let cell = (local - self.origin) / self.cell_size;
+
+        ChunkPos {
+            x: cell.x.floor() as i32,
+            y: self.cell_height.map_or(0, |cell_height| ((local.y - self.origin.y) / cell_height).floor() as i32),
+            z: cell.z.floor() as i32,
+        }
+    }
+
+    pub fn world_to_chunk(&self, world: Vec3) -> ChunkPos {
+        self.local_to_chunk(self.world_to_local(world))
+    }
+
+    /// Local corner of `chunk` with the lowest coordinates. Without a cell height, chunks have no floor.
+    pub fn chunk_min(&self, chunk: ChunkPos) -> Vec3 {
+        Vec3::new(
+            self.origin.x + chunk.x as f32 * self.cell_size,
+            self.cell_height.map_or(f32::NEG_INFINITY, |cell_height| self.origin.y + chunk.y as f32 * cell_height),
+            self.origin.z + chunk.z as f32 * self.cell_size,
+        )
+    }
+
+    /// Local corner of `chunk` with the highest coordinates. Without a cell height, chunks have no ceiling.
+    pub fn chunk_max(&self, chunk: ChunkPos) -> Vec3 {
+        Vec3::new(
+            self.origin.x + (chunk.x + 1) as f32 * self.cell_size,
+            self.cell_height.map_or(f32::INFINITY, |cell_height| self.origin.y + (chunk.y + 1) as f32 * cell_height),
+            self.origin.z + (chunk.z + 1) as f32 * self.cell_size,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collider_divider::{try_split_meshlets, BoundaryMode, SplitSettings};
+    use bevy::prelude::{Mesh, Quat, Transform};
+    use bevy::render::{mesh::{Indices, PrimitiveTopology}, render_asset::RenderAssetUsages};
+
+    fn random_vec3(rng: &mut fastrand::Rng, range: f32) -> Vec3 {
+        Vec3::new(rng.f32() * 2.0 - 1.0, rng.f32() * 2.0 - 1.0, rng.f32() * 2.0 - 1.0) * range
+    }
+
+    fn random_transform(rng: &mut fastrand::Rng) -> GlobalTransform {
+        let rotation = Quat::from_euler(bevy::math::EulerRot::YXZ, rng.f32() * 6.3, rng.f32() * 6.3, rng.f32() * 6.3);
+        let scale = Vec3::new(0.1 + rng.f32() * 30.0, 0.1 + rng.f32() * 30.0, 0.1 + rng.f32() * 30.0);
+
+        Transform::from_translation(random_vec3(rng, 1000.0))
+            .with_rotation(rotation)
+            .with_scale(scale)
+            .into()
+    }
+
+    fn distance_to_triangle(point: Vec3, [a, b, c]: [Vec3; 3]) -> f32 {
+        let normal = (b - a).cross(c - a).normalize();
+        let projected = point - normal * normal.dot(point - a);
+
+        let inside = [(a, b), (b, c), (c, a)].iter()
+            .all(|(start, end)| (*end - *start).cross(projected - *start).dot(normal) >= 0.0);
+        if inside {
+            return (point - projected).length();
+        }
+
+        [(a, b), (b, c), (c, a)].iter()
+            .map(|(start, end)| {
+                let t = ((point - *start).dot(*end - *start) / (*end - *start).length_squared()).clamp(0.0, 1.0);
+                (point - (*start + (*end - *start) * t)).length()
+            })
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    #[test]
+    fn world_local_roundtrip() {
+        let mut rng = fastrand::Rng::with_seed(11);
+
+        for _ in 0..1000 {
+            let grid = ChunkGrid::new(1.0).with_transform(random_transform(&mut rng));
+            let local = random_vec3(&mut rng, 500.0);
+
+            let roundtrip = grid.world_to_local(grid.local_to_world(local));
+            assert!((roundtrip - local).length() < 0.01, "{local} came back as {roundtrip}");
+        }
+    }
+
+    #[test]
+    fn loader_on_triangle_maps_to_its_chunk() {
+        let mut rng = fastrand::Rng::with_seed(7);
+
+        for _ in 0..300 {
+            let cell_size = 1.0 + rng.f32() * 40.0;
+            let cell_height = rng.bool().then(|| 1.0 + rng.f32() * 40.0);
+            let mut settings = SplitSettings::new(cell_size).with_boundary_mode(BoundaryMode::Clip);
+            settings.chunk_height = cell_height;
+
+            let triangle = [random_vec3(&mut rng, 100.0), random_vec3(&mut rng, 100.0), random_vec3(&mut rng, 100.0)];
+            let mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default())
+                .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, triangle.map(|vertex| vertex.to_array()).to_vec())
+                .with_inserted_indices(Indices::U32(vec![0, 1, 2]));
+            let meshlets = try_split_meshlets(&mesh, &settings).unwrap();
+
+            let grid = settings.grid().with_transform(random_transform(&mut rng));
+
+            for _ in 0..10 {
+                // a random point on the triangle, seen from the world
+                let (u, v) = (rng.f32(), rng.f32());
+                let (u, v) = if u + v > 1.0 { (1.0 - u, 1.0 - v) } else { (u, v) };
+                let local = triangle[0] + (triangle[1] - triangle[0]) * u + (triangle[2] - triangle[0]) * v;
+                let chunk = grid.world_to_chunk(grid.local_to_world(local));
+
+                // the chunk the loader lands in holds the part of the triangle under it
+                let meshlet = meshlets.iter().find(|meshlet| meshlet.chunk_pos == chunk)
+                    .unwrap_or_else(|| panic!("{local} maps to {chunk:?}, which has no triangles"));
+                let distance = meshlet.indices.iter()
+                    .map(|indices| distance_to_triangle(local, indices.map(|i| meshlet.vertices[i as usize])))
+                    .fold(f32::INFINITY, f32::min);
+                assert!(distance < 0.01, "{local} is {distance} away from chunk {chunk:?}");
+
+                let (min, max) = (grid.chunk_min(chunk), grid.chunk_max(chunk));
+                assert!(local.cmpge(min - 0.01).all() && local.cmple(max + 0.01).all());
+            }
+        }
+    }
+}
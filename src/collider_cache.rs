@@ -0,0 +1,418 @@
+use bevy::log::{info, warn};
+use bevy::prelude::{Mesh, Vec2, Vec3};
+use bevy::render::mesh::Indices;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use crate::collider_divider::{self, BoundaryMode, ChunkPos, Meshlet, MeshletLod, SplitError, SplitSettings};
+use crate::collider_heightfield::Heightfield;
+use crate::collider_surface::{self, SurfaceId};
+
+const MAGIC: &[u8; 4] = b"VACC";
+// bump this whenever the file layout or the splitting output changes
+const VERSION: u32 = 12;
+
+/// 64-bit FNV-1a - unlike `DefaultHasher`, stable across Rust releases, so cache files survive toolchain updates
+pub(crate) struct Fnv1a(pub(crate) u64);
+
+impl Fnv1a {
+    pub(crate) fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+}
+
+/// Hash of everything that affects the output of [`collider_divider::try_split_meshlets`]
+pub fn cache_key(mesh: &Mesh, settings: &SplitSettings) -> u64 {
+    let mut hasher = Fnv1a::new();
+
+    hasher.write(&VERSION.to_le_bytes());
+    hasher.write(&settings.chunk_size.to_bits().to_le_bytes());
+    hasher.write(&[match settings.boundary_mode {
+        BoundaryMode::Duplicate => 0,
+        BoundaryMode::Clip => 1,
+    }]);
+    match settings.cleanup_tolerance {
+        Some(tolerance) => {
+            hasher.write(&[1]);
+            hasher.write(&tolerance.to_bits().to_le_bytes());
+        },
+        None => hasher.write(&[0]),
+    }
+    hasher.write(&(settings.lods.len() as u32).to_le_bytes());
+    for lod in settings.lods {
+        hasher.write(&lod.target_ratio.to_bits().to_le_bytes());
+        hasher.write(&lod.max_error.to_bits().to_le_bytes());
+    }
+    match settings.heightfield_tolerance {
+        Some(max_error) => {
+            hasher.write(&[1]);
+            hasher.write(&max_error.to_bits().to_le_bytes());
+        },
+        None => hasher.write(&[0]),
+    }
+    match settings.chunk_height {
+        Some(chunk_height) => {
+            hasher.write(&[1]);
+            hasher.write(&chunk_height.to_bits().to_le_bytes());
+        },
+        None => hasher.write(&[0]),
+    }
+
+    hasher.write(&[mesh.primitive_topology() as u8]);
+
+    if let Some(positions) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+        hasher.write(positions.get_bytes());
+    }
+    match collider_surface::vertex_surfaces(mesh) {
+        Some(surfaces) => {
+            hasher.write(&[1]);
+            surfaces.iter().for_each(|surface| hasher.write(&surface.to_le_bytes()));
+        },
+        None => hasher.write(&[0]),
+    }
+
+    // hash indices as u32 either way, so a mesh hashes the same no matter which loader narrowed its indices
+    match mesh.indices() {
+        Some(Indices::U16(indices)) => indices.iter().for_each(|i| hasher.write(&(*i as u32).to_le_bytes())),
+        Some(Indices::U32(indices)) => indices.iter().for_each(|i| hasher.write(&i.to_le_bytes())),
+        None => {},
+    }
+
+    hasher.0
+}
+
+pub fn cache_path(cache_dir: &Path, key: u64) -> PathBuf {
+    cache_dir.join(format!("{key:016x}.bin"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+fn encode_triangles(bytes: &mut Vec<u8>, vertices: &[Vec3], indices: &[[u32; 3]], surfaces: &[SurfaceId]) {
+    bytes.extend_from_slice(&(vertices.len() as u32).to_le_bytes());
+    for vertex in vertices {
+        for component in vertex.to_array() {
+            bytes.extend_from_slice(&component.to_le_bytes());
+        }
+    }
+
+    bytes.extend_from_slice(&(indices.len() as u32).to_le_bytes());
+    for (index, surface) in indices.iter().zip(surfaces) {
+        for component in index {
+            bytes.extend_from_slice(&component.to_le_bytes());
+        }
+        bytes.extend_from_slice(&surface.0.to_le_bytes());
+    }
+}
+
+pub fn encode(key: u64, meshlets: &[Meshlet]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&key.to_le_bytes());
+    bytes.extend_from_slice(&(meshlets.len() as u32).to_le_bytes());
+
+    for meshlet in meshlets {
+        bytes.extend_from_slice(&meshlet.chunk_pos.x.to_le_bytes());
+        bytes.extend_from_slice(&meshlet.chunk_pos.y.to_le_bytes());
+        bytes.extend_from_slice(&meshlet.chunk_pos.z.to_le_bytes());
+        encode_triangles(&mut bytes, &meshlet.vertices, &meshlet.indices, &meshlet.surfaces);
+
+        bytes.extend_from_slice(&(meshlet.lods.len() as u32).to_le_bytes());
+        for lod in &meshlet.lods {
+            encode_triangles(&mut bytes, &lod.vertices, &lod.indices, &lod.surfaces);
+        }
+
+        // a resolution of 0 means the chunk has no heightfield
+        match &meshlet.heightfield {
+            Some(heightfield) => {
+                bytes.extend_from_slice(&(heightfield.resolution() as u32).to_le_bytes());
+                for component in [heightfield.min.x, heightfield.min.y, heightfield.size.x, heightfield.size.y] {
+                    bytes.extend_from_slice(&component.to_le_bytes());
+                }
+                for height in heightfield.heights.iter().flatten() {
+                    bytes.extend_from_slice(&height.to_le_bytes());
+                }
+            },
+            None => bytes.extend_from_slice(&0u32.to_le_bytes()),
+        }
+    }
+
+    // trailing checksum catches truncated or partially overwritten files
+    let mut hasher = Fnv1a::new();
+    hasher.write(&bytes);
+    bytes.extend_from_slice(&hasher.0.to_le_bytes());
+
+    bytes
+}
+
+/// Bounds-checked little endian reader, so a corrupt length can't make us allocate or read garbage
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(invalid("unexpected end of collider cache"));
+        }
+
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> io::Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// Reads an element count, rejecting counts that can't possibly fit in the remaining bytes
+    fn count(&mut self, element_size: usize) -> io::Result<usize> {
+        let count = self.u32()? as usize;
+        if count.saturating_mul(element_size) > self.bytes.len() {
+            return Err(invalid("collider cache count out of range"));
+        }
+        Ok(count)
+    }
+
+    fn triangles(&mut self) -> io::Result<MeshletLod> {
+        let vertex_count = self.count(12)?;
+        let mut vertices = Vec::with_capacity(vertex_count);
+        for _ in 0..vertex_count {
+            vertices.push(Vec3::new(self.f32()?, self.f32()?, self.f32()?));
+        }
+
+        let index_count = self.count(16)?;
+        let mut indices = Vec::with_capacity(index_count);
+        let mut surfaces = Vec::with_capacity(index_count);
+        for _ in 0..index_count {
+            let index = [self.u32()?, self.u32()?, self.u32()?];
+            if index.iter().any(|i| *i as usize >= vertex_count) {
+                return Err(invalid("collider cache index out of range"));
+            }
+            indices.push(index);
+            surfaces.push(SurfaceId(self.u32()?));
+        }
+
+        Ok(MeshletLod { vertices, indices, surfaces })
+    }
+
+    fn heightfield(&mut self) -> io::Result<Option<Heightfield>> {
+        let resolution = self.u32()? as usize;
+        if resolution == 0 {
+            return Ok(None);
+        }
+        if resolution < 2 || resolution.saturating_mul(resolution).saturating_mul(4) > self.bytes.len() {
+            return Err(invalid("collider cache heightfield resolution out of range"));
+        }
+
+        let min = Vec2::new(self.f32()?, self.f32()?);
+        let size = Vec2::new(self.f32()?, self.f32()?);
+        let mut heights = Vec::with_capacity(resolution);
+        for _ in 0..resolution {
+            heights.push((0..resolution).map(|_| self.f32()).collect::<io::Result<Vec<f32>>>()?);
+        }
+
+        Ok(Some(Heightfield { heights, min, size }))
+    }
+}
+
+pub fn decode(expected_key: u64, bytes: &[u8]) -> io::Result<Vec<Meshlet>> {
+    if bytes.len() < 8 {
+        return Err(invalid("collider cache too short"));
+    }
+
+    let (payload, checksum) = bytes.split_at(bytes.len() - 8);
+    let mut hasher = Fnv1a::new();
+    hasher.write(payload);
+    if hasher.0.to_le_bytes() != checksum {
+        return Err(invalid("collider cache checksum mismatch"));
+    }
+
+    let mut reader = Reader { bytes: payload };
+
+    if reader.take(4)? != MAGIC {
+        return Err(invalid("not a collider cache"));
+    }
+    if reader.u32()? != VERSION {
+        return Err(invalid("collider cache version mismatch"));
+    }
+    if reader.u64()? != expected_key {
+        return Err(invalid("collider cache was built from a different mesh"));
+    }
+
+    let meshlet_count = reader.count(24)?;
+    let mut meshlets = Vec::with_capacity(meshlet_count);
+
+    for _ in 0..meshlet_count {
+        let chunk_pos = ChunkPos {
+            x: reader.i32()?,
+            y: reader.i32()?,
+            z: reader.i32()?,
+        };
+        let MeshletLod { vertices, indices, surfaces } = reader.triangles()?;
+
+        let lod_count = reader.count(8)?;
+        let mut lods = Vec::with_capacity(lod_count);
+        for _ in 0..lod_count {
+            lods.push(reader.triangles()?);
+        }
+        let heightfield = reader.heightfield()?;
+
+        meshlets.push(Meshlet { chunk_pos, vertices, indices, surfaces, lods, heightfield });
+    }
+
+    if !reader.bytes.is_empty() {
+        return Err(invalid("trailing data in collider cache"));
+    }
+
+    Ok(meshlets)
+}
+
+/// Writes `meshlets` to the cache file for `key`, returning its path
+pub fn store(cache_dir: &Path, key: u64, meshlets: &[Meshlet]) -> io::Result<PathBuf> {
+    let path = cache_path(cache_dir, key);
+    fs::create_dir_all(cache_dir)?;
+
+    // write next to the target and rename, so a crash mid-write never leaves a half written cache behind
+    let tmp_path = path.with_extension("bin.tmp");
+    fs::write(&tmp_path, encode(key, meshlets))?;
+    fs::rename(&tmp_path, &path)?;
+
+    Ok(path)
+}
+
+/// Loads the chunks of `mesh` from `cache_dir`, splitting and caching them if no valid cache file exists.
+/// Meshes that can't be split are never cached.
+pub fn load_or_split(mesh: &Mesh, settings: &SplitSettings, cache_dir: &Path) -> Result<Vec<Meshlet>, SplitError> {
+    let key = cache_key(mesh, settings);
+    let path = cache_path(cache_dir, key);
+
+    match fs::read(&path).and_then(|bytes| decode(key, &bytes)) {
+        Ok(meshlets) => return Ok(meshlets),
+        Err(err) if err.kind() == ErrorKind::NotFound => {},
+        Err(err) => warn!("Rebuilding collider cache {}: {}", path.display(), err),
+    }
+
+    let meshlets = collider_divider::try_split_meshlets(mesh, settings)?;
+
+    match store(cache_dir, key, &meshlets) {
+        Ok(path) => info!("Wrote collider cache {}", path.display()),
+        Err(err) => warn!("Failed to write collider cache {}: {}", path.display(), err),
+    }
+
+    Ok(meshlets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meshlet() -> Meshlet {
+        Meshlet {
+            chunk_pos: ChunkPos { x: -3, y: 1, z: 7 },
+            vertices: vec![Vec3::new(0.0, 1.0, 2.0), Vec3::new(-1.5, 0.25, 3.0), Vec3::new(4.0, -2.0, 0.5), Vec3::new(1.0, 1.0, 1.0)],
+            indices: vec![[0, 1, 2], [2, 1, 3]],
+            surfaces: vec![SurfaceId(1), SurfaceId(u32::MAX)],
+            lods: vec![MeshletLod {
+                vertices: vec![Vec3::new(0.0, 1.0, 2.0), Vec3::new(-1.5, 0.25, 3.0), Vec3::new(4.0, -2.0, 0.5)],
+                indices: vec![[0, 1, 2]],
+                surfaces: vec![SurfaceId(1)],
+            }],
+            heightfield: Some(Heightfield {
+                heights: vec![vec![0.0, 0.5, 1.0], vec![-0.5, 0.0, 0.5], vec![2.0, 1.5, f32::MIN_POSITIVE]],
+                min: Vec2::new(-4.0, 2.5),
+                size: Vec2::new(15.0, 7.5),
+            }),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let meshlets = vec![meshlet(), Meshlet { chunk_pos: ChunkPos { x: 0, y: 0, z: 0 }, lods: Vec::new(), heightfield: None, ..meshlet() }];
+
+        assert_eq!(decode(42, &encode(42, &meshlets)).unwrap(), meshlets);
+        assert_eq!(decode(42, &encode(42, &[])).unwrap(), Vec::new());
+    }
+
+    /// `payload` with a checksum that matches it, so only the checks after the checksum can reject it
+    fn with_checksum(mut payload: Vec<u8>) -> Vec<u8> {
+        let mut hasher = Fnv1a::new();
+        hasher.write(&payload);
+        payload.extend_from_slice(&hasher.0.to_le_bytes());
+        payload
+    }
+
+    fn payload(key: u64, meshlets: &[Meshlet]) -> Vec<u8> {
+        let mut bytes = encode(key, meshlets);
+        bytes.truncate(bytes.len() - 8);
+        bytes
+    }
+
+    #[test]
+    fn rejects_wrong_header() {
+        let mut wrong_magic = payload(42, &[meshlet()]);
+        wrong_magic[..4].copy_from_slice(b"VACX");
+        let mut wrong_version = payload(42, &[meshlet()]);
+        wrong_version[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+
+        assert_eq!(decode(42, &with_checksum(wrong_magic)).unwrap_err().to_string(), "not a collider cache");
+        assert_eq!(decode(42, &with_checksum(wrong_version)).unwrap_err().to_string(), "collider cache version mismatch");
+        assert_eq!(decode(43, &encode(42, &[meshlet()])).unwrap_err().to_string(), "collider cache was built from a different mesh");
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let bytes = encode(42, &[meshlet()]);
+        for len in 0..bytes.len() {
+            assert_eq!(decode(42, &bytes[..len]).unwrap_err().kind(), ErrorKind::InvalidData, "{len} bytes were accepted");
+        }
+
+        // a file cut short and given a matching checksum still runs out of bytes, wherever it was cut
+        let payload = payload(42, &[meshlet()]);
+        for len in 0..payload.len() {
+            assert_eq!(decode(42, &with_checksum(payload[..len].to_vec())).unwrap_err().kind(), ErrorKind::InvalidData, "{len} bytes were accepted");
+        }
+    }
+
+    #[test]
+    fn load_or_split_rebuilds_corrupt_cache() {
+        use bevy::render::{mesh::PrimitiveTopology, render_asset::RenderAssetUsages};
+
+        let mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default())
+            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, vec![[0.1, 0.0, 0.1], [0.9, 0.0, 0.1], [0.1, 0.0, 0.9]])
+            .with_inserted_indices(Indices::U32(vec![0, 2, 1]));
+        let settings = SplitSettings::new(1.0);
+        let cache_dir = std::env::temp_dir().join(format!("voyage-abeon-collider-cache-{}", std::process::id()));
+        let path = cache_path(&cache_dir, cache_key(&mesh, &settings));
+
+        fs::create_dir_all(&cache_dir).unwrap();
+        fs::write(&path, b"VACC garbage").unwrap();
+        let meshlets = load_or_split(&mesh, &settings, &cache_dir).unwrap();
+        assert_eq!(meshlets, collider_divider::try_split_meshlets(&mesh, &settings).unwrap());
+        assert_eq!(decode(cache_key(&mesh, &settings), &fs::read(&path).unwrap()).unwrap(), meshlets);
+
+        fs::remove_dir_all(&cache_dir).unwrap();
+    }
+}
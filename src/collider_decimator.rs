@@ -0,0 +1,393 @@
+use bevy::prelude::Vec3;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+/// Symmetric 4x4 error quadric (Garland & Heckbert), upper triangle stored row by row
+#[derive(Copy, Clone, Debug, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(a: f64, b: f64, c: f64, d: f64) -> Self {
+        Self([a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d])
+    }
+
+    fn add(&self, other: &Quadric) -> Quadric {
+        let mut sum = self.0;
+        for (total, value) in sum.iter_mut().zip(other.0) {
+            *total += value;
+        }
+        Quadric(sum)
+    }
+
+    /// Sum of squared distances from `point` to every plane in the quadric
+    fn error(&self, point: Vec3) -> f64 {
+        let (x, y, z) = (point.x as f64, point.y as f64, point.z as f64);
+        let q = &self.0;
+
+        q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x
+            + q[4] * y * y + 2.0 * q[5] * y * z + 2.0 * q[6] * y
+            + q[7] * z * z + 2.0 * q[8] * z
+            + q[9]
+    }
+}
+
+/// A possible edge collapse, moving `from` into `into` at `target`
+#[derive(Copy, Clone, Debug)]
+struct Collapse {
+    cost: f64,
+    into: u32,
+    from: u32,
+    target: Vec3,
+    // versions of both vertices when the cost was computed, to skip outdated entries
+    into_version: u32,
+    from_version: u32,
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    // reversed, so the cheapest collapse is on top of the heap
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+/// Cosine of the furthest a triangle may turn away from its source triangle, about 75 degrees.
+/// Without it, collapses that each turn a triangle a little can stand it up on its edge.
+const MAX_NORMAL_TURN: f32 = 0.25;
+
+struct Decimator {
+    positions: Vec<Vec3>,
+    quadrics: Vec<Quadric>,
+    versions: Vec<u32>,
+    // vertices on an open or non-manifold edge never move, so neighbouring chunks keep meeting
+    locked: Vec<bool>,
+    triangles: Vec<[u32; 3]>,
+    // facing of every source triangle, so collapses can't turn a triangle over a little at a time
+    normals: Vec<Vec3>,
+    alive: Vec<bool>,
+    vertex_triangles: Vec<Vec<usize>>,
+}
+
+impl Decimator {
+    fn new(vertices: &[Vec3], indices: &[[u32; 3]]) -> Self {
+        let mut quadrics = vec![Quadric::default(); vertices.len()];
+        let mut vertex_triangles = vec![Vec::new(); vertices.len()];
+        let mut edge_use: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut normals = Vec::with_capacity(indices.len());
+
+        for (triangle_idx, triangle) in indices.iter().enumerate() {
+            let [a, b, c] = triangle.map(|i| vertices[i as usize]);
+            let normal = (b - a).cross(c - a).normalize_or_zero();
+            normals.push(normal);
+            let plane = Quadric::from_plane(normal.x as f64, normal.y as f64, normal.z as f64, -normal.dot(a) as f64);
+
+            for (corner, vertex_idx) in triangle.iter().enumerate() {
+                quadrics[*vertex_idx as usize] = quadrics[*vertex_idx as usize].add(&plane);
+                vertex_triangles[*vertex_idx as usize].push(triangle_idx);
+
+                let next = triangle[(corner + 1) % 3];
+                *edge_use.entry((*vertex_idx.min(&next), *vertex_idx.max(&next))).or_default() += 1;
+            }
+        }
+
+        let mut locked = vec![false; vertices.len()];
+        for ((a, b), uses) in edge_use {
+            if uses != 2 {
+                locked[a as usize] = true;
+                locked[b as usize] = true;
+            }
+        }
+
+        Self {
+            positions: vertices.to_vec(),
+            quadrics,
+            versions: vec![0; vertices.len()],
+            locked,
+            triangles: indices.to_vec(),
+            normals,
+            alive: vec![true; indices.len()],
+            vertex_triangles,
+        }
+    }
+
+    fn neighbours(&self, vertex: u32) -> Vec<u32> {
+        let mut neighbours: Vec<u32> = self.vertex_triangles[vertex as usize].iter()
+            .filter(|triangle_idx| self.alive[**triangle_idx])
+            .flat_map(|triangle_idx| self.triangles[*triangle_idx])
+            .filter(|other| *other != vertex)
+            .collect();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        neighbours
+    }
+
+    fn candidate(&self, a: u32, b: u32) -> Option<Collapse> {
+        let (into, from) = match (self.locked[a as usize], self.locked[b as usize]) {
+            (true, true) => return None,
+            (false, true) => (b, a),
+            _ => (a, b),
+        };
+
+        let quadric = self.quadrics[into as usize].add(&self.quadrics[from as usize]);
+        let into_pos = self.positions[into as usize];
+        let from_pos = self.positions[from as usize];
+
+        // a locked vertex has to stay where it is, otherwise pick the best of the ends and the midpoint
+        let targets: &[Vec3] = if self.locked[into as usize] {
+            &[into_pos]
+        } else {
+            &[into_pos, from_pos, (into_pos + from_pos) / 2.0]
+        };
+
+        let (cost, target) = targets.iter()
+            .map(|target| (quadric.error(*target), *target))
+            .min_by(|x, y| x.0.total_cmp(&y.0))?;
+
+        Some(Collapse {
+            cost,
+            into,
+            from,
+            target,
+            into_version: self.versions[into as usize],
+            from_version: self.versions[from as usize],
+        })
+    }
+
+    /// Whether moving `from` and `into` to `target` would flip or flatten any surviving triangle,
+    /// or turn it more than [`MAX_NORMAL_TURN`] away from the source triangle it started as
+    fn flips(&self, collapse: &Collapse) -> bool {
+        [collapse.into, collapse.from].iter()
+            .flat_map(|vertex| &self.vertex_triangles[*vertex as usize])
+            .filter(|triangle_idx| self.alive[**triangle_idx])
+            .map(|triangle_idx| (*triangle_idx, self.triangles[*triangle_idx]))
+            // triangles on the collapsed edge disappear, so they can't flip
+            .filter(|(_, triangle)| !(triangle.contains(&collapse.into) && triangle.contains(&collapse.from)))
+            .any(|(triangle_idx, triangle)| {
+                let [a, b, c] = triangle.map(|i| self.positions[i as usize]);
+                let [new_a, new_b, new_c] = triangle.map(|i| {
+                    if i == collapse.into || i == collapse.from { collapse.target } else { self.positions[i as usize] }
+                });
+
+                let before = (b - a).cross(c - a);
+                let after = (new_b - new_a).cross(new_c - new_a);
+                before.dot(after) <= 0.0 || self.normals[triangle_idx].dot(after.normalize_or_zero()) < MAX_NORMAL_TURN
+            })
+    }
+
+    /// Corners of the surviving triangles around `vertex` that don't use `other`, with the edges across from `vertex`
+    fn link(&self, vertex: u32, other: u32) -> (Vec<u32>, Vec<(u32, u32)>) {
+        let mut corners = Vec::new();
+        let mut edges = Vec::new();
+
+        for triangle in self.vertex_triangles[vertex as usize].iter()
+            .filter(|triangle_idx| self.alive[**triangle_idx])
+            .map(|triangle_idx| self.triangles[*triangle_idx])
+            .filter(|triangle| !triangle.contains(&other)) {
+            let rest: Vec<u32> = triangle.into_iter().filter(|corner| *corner != vertex).collect();
+            if let [a, b] = rest[..] {
+                corners.extend([a, b]);
+                edges.push((a.min(b), a.max(b)));
+            }
+        }
+
+        corners.sort_unstable();
+        corners.dedup();
+        (corners, edges)
+    }
+
+    /// The link condition: the only vertices both ends of the edge share are the ones across the triangles on the edge,
+    /// and they share no edges at all. Anything else would glue two sheets of the surface together along a new edge.
+    fn keeps_manifold(&self, collapse: &Collapse) -> bool {
+        let (into_corners, into_edges) = self.link(collapse.into, collapse.from);
+        let (from_corners, from_edges) = self.link(collapse.from, collapse.into);
+        let opposite: Vec<u32> = self.vertex_triangles[collapse.into as usize].iter()
+            .filter(|triangle_idx| self.alive[**triangle_idx])
+            .map(|triangle_idx| self.triangles[*triangle_idx])
+            .filter(|triangle| triangle.contains(&collapse.from))
+            .flatten()
+            .filter(|corner| *corner != collapse.into && *corner != collapse.from)
+            .collect();
+
+        into_corners.iter()
+            .filter(|corner| from_corners.binary_search(corner).is_ok())
+            .all(|corner| opposite.contains(corner))
+            && !into_edges.iter().any(|edge| from_edges.contains(edge))
+    }
+
+    /// Merges `from` into `into`, returning how many triangles were removed
+    fn collapse(&mut self, collapse: &Collapse) -> usize {
+        let (into, from) = (collapse.into, collapse.from);
+        let mut removed = 0;
+
+        self.positions[into as usize] = collapse.target;
+        self.quadrics[into as usize] = self.quadrics[into as usize].add(&self.quadrics[from as usize]);
+        self.versions[into as usize] += 1;
+        self.versions[from as usize] += 1;
+
+        for triangle_idx in std::mem::take(&mut self.vertex_triangles[from as usize]) {
+            if !self.alive[triangle_idx] {
+                continue;
+            }
+
+            let triangle = &mut self.triangles[triangle_idx];
+            if triangle.contains(&into) {
+                self.alive[triangle_idx] = false;
+                removed += 1;
+            } else {
+                for corner in triangle.iter_mut() {
+                    if *corner == from {
+                        *corner = into;
+                    }
+                }
+                self.vertex_triangles[into as usize].push(triangle_idx);
+            }
+        }
+
+        removed
+    }
+
+    fn into_mesh(self) -> (Vec<Vec3>, Vec<[u32; 3]>) {
+        let mut remap: HashMap<u32, u32> = HashMap::new();
+        let mut vertices = Vec::new();
+
+        let indices = self.triangles.iter()
+            .zip(&self.alive)
+            .filter(|(_, alive)| **alive)
+            .map(|(triangle, _)| triangle.map(|vertex| *remap.entry(vertex).or_insert_with(|| {
+                vertices.push(self.positions[vertex as usize]);
+                vertices.len() as u32 - 1
+            })))
+            .collect();
+
+        (vertices, indices)
+    }
+}
+
+/// Simplifies a triangle mesh by collapsing its cheapest edges first, until only `target_ratio` of the
+/// triangles are left or the next collapse would move the surface further than `max_error`.
+/// Open edges are never moved, so a decimated chunk still lines up with its neighbours.
+pub fn decimate(vertices: &[Vec3], indices: &[[u32; 3]], target_ratio: f32, max_error: f32) -> (Vec<Vec3>, Vec<[u32; 3]>) {
+    let mut decimator = Decimator::new(vertices, indices);
+    let target_triangles = (indices.len() as f32 * target_ratio.clamp(0.0, 1.0)).ceil() as usize;
+
+    let mut heap = BinaryHeap::new();
+    for vertex in 0..vertices.len() as u32 {
+        for neighbour in decimator.neighbours(vertex) {
+            if vertex < neighbour {
+                heap.extend(decimator.candidate(vertex, neighbour));
+            }
+        }
+    }
+
+    let mut triangle_count = indices.len();
+    while triangle_count > target_triangles {
+        let Some(collapse) = heap.pop() else {
+            break;
+        };
+
+        if collapse.into_version != decimator.versions[collapse.into as usize]
+            || collapse.from_version != decimator.versions[collapse.from as usize] {
+            continue;
+        }
+        // the square root of the summed squared plane distances bounds how far the target is from every merged plane
+        if collapse.cost.sqrt() > max_error as f64 {
+            break;
+        }
+        if !decimator.keeps_manifold(&collapse) || decimator.flips(&collapse) {
+            continue;
+        }
+
+        triangle_count -= decimator.collapse(&collapse);
+
+        for neighbour in decimator.neighbours(collapse.into) {
+            heap.extend(decimator.candidate(collapse.into, neighbour));
+        }
+    }
+
+    decimator.into_mesh()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A flat `size` x `size` grid of quads in the XZ plane
+    fn grid(size: u32) -> (Vec<Vec3>, Vec<[u32; 3]>) {
+        let vertices = (0..=size)
+            .flat_map(|z| (0..=size).map(move |x| Vec3::new(x as f32, 0.0, z as f32)))
+            .collect();
+        let indices = (0..size)
+            .flat_map(|z| (0..size).flat_map(move |x| {
+                let corner = z * (size + 1) + x;
+                [[corner, corner + size + 1, corner + 1], [corner + 1, corner + size + 1, corner + size + 2]]
+            }))
+            .collect();
+
+        (vertices, indices)
+    }
+
+    #[test]
+    fn flat_grid_collapses_without_error() {
+        let (vertices, indices) = grid(8);
+        let (decimated_vertices, decimated_indices) = decimate(&vertices, &indices, 0.0, 0.0001);
+
+        assert!(decimated_indices.len() < indices.len() / 2);
+        // the grid stays flat and its border stays where it was
+        assert!(decimated_vertices.iter().all(|vertex| vertex.y.abs() < 0.0001));
+        let border: Vec<&Vec3> = vertices.iter().filter(|v| v.x == 0.0 || v.x == 8.0 || v.z == 0.0 || v.z == 8.0).collect();
+        assert!(border.iter().all(|vertex| decimated_vertices.contains(vertex)));
+    }
+
+    #[test]
+    fn collapses_keep_the_mesh_manifold() {
+        let mut rng = fastrand::Rng::with_seed(8);
+
+        for _ in 0..20 {
+            let (mut vertices, indices) = grid(10);
+            for vertex in &mut vertices {
+                vertex.y = rng.f32() * 0.1;
+            }
+
+            let (decimated_vertices, decimated_indices) = decimate(&vertices, &indices, 0.0, 0.25);
+            assert!(decimated_indices.len() < indices.len());
+
+            let mut edge_use: HashMap<(u32, u32), u32> = HashMap::new();
+            for triangle in &decimated_indices {
+                assert!(triangle[0] != triangle[1] && triangle[1] != triangle[2] && triangle[0] != triangle[2], "collapsed triangle {triangle:?} is left");
+                for corner in 0..3 {
+                    let (a, b) = (triangle[corner], triangle[(corner + 1) % 3]);
+                    *edge_use.entry((a.min(b), a.max(b))).or_default() += 1;
+                }
+
+                // seen from above the grid has no overhangs, so a triangle facing down or standing on its edge folded over
+                let [a, b, c] = triangle.map(|i| decimated_vertices[i as usize]);
+                assert!((b - a).cross(c - a).y > 0.0, "triangle {triangle:?} folded over");
+            }
+            assert!(edge_use.values().all(|uses| *uses <= 2), "an edge is shared by more than two triangles");
+        }
+    }
+
+    #[test]
+    fn error_bound_keeps_bumps() {
+        let (mut vertices, indices) = grid(4);
+        // a spike in the middle of the grid
+        vertices[12].y = 3.0;
+
+        let (decimated_vertices, _) = decimate(&vertices, &indices, 0.0, 0.1);
+
+        assert!(decimated_vertices.contains(&Vec3::new(2.0, 3.0, 2.0)));
+    }
+}
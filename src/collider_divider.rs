@@ -1,15 +1,73 @@
-use bevy::render::mesh::{Indices, VertexAttributeValues};
+use bevy::render::mesh::{Indices, MeshVertexAttributeId, PrimitiveTopology, VertexAttributeValues};
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::VertexFormat;
+use bevy::color::{Hsla, Srgba};
 use bevy::prelude::Mesh;
-use bevy::prelude::Vec3;
+use bevy::prelude::{BVec3, Vec3};
+use bevy::log::{info, warn};
 use itertools::Itertools;
 use avian3d::collision::collider::Collider;
-use std::collections::HashMap;
+use bevy::tasks::TaskPool;
+use crate::chunk_grid::ChunkGrid;
+use crate::collider_decimator;
+use crate::collider_heightfield::{self, Heightfield};
+use crate::collider_surface::{self, SurfaceId, SurfaceLookup};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+use std::fs;
+use std::io;
+use std::path::Path;
 
+/// Why a mesh couldn't be split into subcolliders
+#[derive(Clone, Debug, PartialEq)]
+pub enum SplitError {
+    /// The mesh has no `Mesh::ATTRIBUTE_POSITION`
+    MissingPositions,
+    /// The positions are stored in a format we can't read
+    UnsupportedPositionFormat(VertexFormat),
+    /// The mesh is made of points or lines, which have no surface to collide with
+    UnsupportedTopology(PrimitiveTopology),
+    /// A triangle refers to a vertex that doesn't exist
+    IndexOutOfRange { index: u32, vertex_count: usize },
+    /// The mesh has more vertices than a `u32` index can address
+    TooManyVertices(usize),
+    /// A vertex position is NaN, infinite or so far from the grid origin that its chunk position doesn't fit in an `i32`
+    VertexOutOfGrid(u32),
+    /// Render meshes can only be split from triangle lists
+    UnsupportedRenderTopology(PrimitiveTopology),
+    /// A vertex attribute of a render mesh has a different number of values than the mesh has positions
+    AttributeLengthMismatch { attribute: MeshVertexAttributeId, len: usize, vertex_count: usize },
+    /// A triangle list whose index count (or vertex count, if it has no indices) isn't a multiple of 3
+    IncompleteTriangle(usize),
+}
+
+impl fmt::Display for SplitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SplitError::MissingPositions => write!(f, "mesh has no vertex positions"),
+            SplitError::UnsupportedPositionFormat(format) => write!(f, "unsupported vertex position format {format:?}"),
+            SplitError::UnsupportedTopology(topology) => write!(f, "unsupported primitive topology {topology:?}, only triangle lists and strips have a surface"),
+            SplitError::IndexOutOfRange { index, vertex_count } => write!(f, "index {index} is out of range for {vertex_count} vertices"),
+            SplitError::TooManyVertices(vertex_count) => write!(f, "{vertex_count} vertices can't be indexed with u32"),
+            SplitError::VertexOutOfGrid(index) => write!(f, "vertex {index} lies outside of the chunk grid"),
+            SplitError::UnsupportedRenderTopology(topology) => write!(f, "unsupported render mesh topology {topology:?}, only triangle lists can be split"),
+            SplitError::AttributeLengthMismatch { attribute, len, vertex_count } => write!(f, "vertex attribute {attribute:?} has {len} values for {vertex_count} vertices"),
+            SplitError::IncompleteTriangle(index_count) => write!(f, "{index_count} indices don't make up whole triangles"),
+        }
+    }
+}
+
+impl std::error::Error for SplitError {}
+
+/// A triangle's vertices, and what its surface is made of
 #[derive(Copy, Clone, Debug, PartialEq)]
 struct Index {
     x: u32,
     y: u32,
     z: u32,
+    surface: SurfaceId,
+    // position of the mesh triangle this one came from, kept through cleanup and splitting
+    source: u32,
 }
 
 impl From<[u32;3]> for Index {
@@ -18,6 +76,8 @@ impl From<[u32;3]> for Index {
             x: raw_index[0],
             y: raw_index[1],
             z: raw_index[2],
+            surface: SurfaceId::default(),
+            source: 0,
         }
     }
 }
@@ -59,19 +119,110 @@ impl From<Vec3> for Vertex {
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
-pub struct ChunkPos {
-    pub x: i32,   
-    pub z: i32,
+impl Vertex {
+    fn lerp(&self, other: &Vertex, t: f32) -> Vertex {
+        Vertex {
+            x: self.x + (other.x - self.x) * t,
+            y: self.y + (other.y - self.y) * t,
+            z: self.z + (other.z - self.z) * t,
+        }
+    }
+
+    fn to_vec3(self) -> Vec3 {
+        Vec3::new(self.x, self.y, self.z)
+    }
+
+    /// Key used to merge bit-identical vertices created while clipping
+    fn bits(&self) -> [u32; 3] {
+        [self.x.to_bits(), self.y.to_bits(), self.z.to_bits()]
+    }
+}
+
+/// How triangles that cover more than one chunk are handled by [`split_mesh`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum BoundaryMode {
+    /// Copy the whole triangle into every chunk one of its vertices lies in.
+    /// Chunks overlap by up to a triangle, but no new vertices are created.
+    #[default]
+    Duplicate,
+    /// Clip the triangle against the chunk grid planes, so every chunk only
+    /// keeps the part of the surface inside its own cell.
+    Clip,
+}
+
+/// One decimated level of detail, built from the level before it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LodLevel {
+    /// Fraction of the previous level's triangles to keep
+    pub target_ratio: f32,
+    /// How far the decimated surface may move away from the previous level
+    pub max_error: f32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SplitSettings {
+    /// Edge length of a chunk along x and z, and of the smallest leaves of a [`ChunkTree`] built over them
+    pub chunk_size: f32,
+    pub boundary_mode: BoundaryMode,
+    /// Welds vertices closer than this and drops degenerate and duplicate triangles before splitting
+    pub cleanup_tolerance: Option<f32>,
+    /// Coarser versions of every chunk to build, finest first
+    pub lods: &'static [LodLevel],
+    /// Replaces the full detail trimesh of chunks that fit a regular height grid this closely with a heightfield
+    pub heightfield_tolerance: Option<f32>,
+    /// Also splits the mesh into layers this high, so caves and tall structures aren't one column per chunk
+    pub chunk_height: Option<f32>,
 }
 
-impl ChunkPos {
-    pub fn from_vertex(vertex: &Vertex, chunk_size: &f32) -> ChunkPos {
-        ChunkPos {
-            x: (vertex.x / (*chunk_size / 2.0)).floor() as i32,
-            z: (vertex.z / (*chunk_size / 2.0)).floor() as i32,
+impl SplitSettings {
+    pub const fn new(chunk_size: f32) -> Self {
+        Self {
+            chunk_size,
+            boundary_mode: BoundaryMode::Duplicate,
+            cleanup_tolerance: None,
+            lods: &[],
+            heightfield_tolerance: None,
+            chunk_height: None,
         }
     }
+
+    pub const fn with_boundary_mode(mut self, boundary_mode: BoundaryMode) -> Self {
+        self.boundary_mode = boundary_mode;
+        self
+    }
+
+    pub const fn with_cleanup(mut self, tolerance: f32) -> Self {
+        self.cleanup_tolerance = Some(tolerance);
+        self
+    }
+
+    pub const fn with_lods(mut self, lods: &'static [LodLevel]) -> Self {
+        self.lods = lods;
+        self
+    }
+
+    pub const fn with_heightfields(mut self, max_error: f32) -> Self {
+        self.heightfield_tolerance = Some(max_error);
+        self
+    }
+
+    pub const fn with_chunk_height(mut self, chunk_height: f32) -> Self {
+        self.chunk_height = Some(chunk_height);
+        self
+    }
+
+    /// The grid the mesh is split on, in the mesh's own space
+    pub const fn grid(&self) -> ChunkGrid {
+        ChunkGrid::new(self.chunk_size).with_cell_height(self.chunk_height)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ChunkPos {
+    pub x: i32,   
+    /// Always 0 unless the mesh was split with a chunk height
+    pub y: i32,
+    pub z: i32,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -79,30 +230,371 @@ struct ChunkData {
     vertices: Vec<Vertex>,
     indices: Vec<Index>,
     global_local_index_map: HashMap<u32, u32>,
+    // vertices created by clipping have no global index, so they are merged by position instead
+    clipped_vertex_map: HashMap<[u32; 3], u32>,
 }
 
-fn split_mesh(vertices: Vec<Vertex>, indices: Vec<Index>, chunk_size: f32) -> HashMap<ChunkPos, (Vec<Vertex>, Vec<Index>)> {
-    let mut chunks: HashMap<ChunkPos, ChunkData> = HashMap::new();
-
-    // assign vertices to chunks
-    for (global_idx, vertex) in vertices.iter().enumerate() {
-        let chunk_pos = ChunkPos::from_vertex(&vertex, &chunk_size);
-        let entry = chunks.entry(chunk_pos).or_insert(ChunkData {
+impl ChunkData {
+    fn new() -> Self {
+        Self {
             vertices: Vec::new(),
             indices: Vec::new(),
-            global_local_index_map: HashMap::new()
+            global_local_index_map: HashMap::new(),
+            clipped_vertex_map: HashMap::new(),
+        }
+    }
+
+    /// Returns the local index of a clipped polygon corner, adding it to the chunk if needed
+    fn local_index(&mut self, corner: &ClipVertex) -> u32 {
+        let next_idx = self.vertices.len() as u32;
+        let local_idx = match corner.global_idx {
+            Some(global_idx) => *self.global_local_index_map.entry(global_idx).or_insert(next_idx),
+            None => *self.clipped_vertex_map.entry(corner.vertex.bits()).or_insert(next_idx),
+        };
+
+        if local_idx == next_idx {
+            self.vertices.push(corner.vertex);
+        }
+
+        local_idx
+    }
+}
+
+/// A corner of a triangle being clipped - either a source vertex or a new point on a grid plane
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct ClipVertex {
+    vertex: Vertex,
+    global_idx: Option<u32>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    fn of(&self, vertex: &Vertex) -> f32 {
+        match self {
+            Axis::X => vertex.x,
+            Axis::Y => vertex.y,
+            Axis::Z => vertex.z,
+        }
+    }
+
+    fn set(&self, vertex: &mut Vertex, value: f32) {
+        match self {
+            Axis::X => vertex.x = value,
+            Axis::Y => vertex.y = value,
+            Axis::Z => vertex.z = value,
+        }
+    }
+}
+
+/// Point where the edge `a`-`b` crosses the plane `axis == plane`
+fn intersect(a: &ClipVertex, b: &ClipVertex, axis: Axis, plane: f32) -> ClipVertex {
+    // always interpolate in the same direction, so both triangles sharing an edge get the same point
+    let (from, to) = if axis.of(&a.vertex) <= axis.of(&b.vertex) { (a, b) } else { (b, a) };
+    let t = (plane - axis.of(&from.vertex)) / (axis.of(&to.vertex) - axis.of(&from.vertex));
+
+    let mut vertex = from.vertex.lerp(&to.vertex, t);
+    // snap onto the plane so the clipped part never leaks out of its cell
+    axis.set(&mut vertex, plane);
+
+    ClipVertex { vertex, global_idx: None }
+}
+
+/// Sutherland-Hodgman clip of a convex polygon against a single grid plane
+fn clip_half_plane(polygon: &[ClipVertex], axis: Axis, plane: f32, keep_above: bool) -> Vec<ClipVertex> {
+    let inside = |corner: &ClipVertex| {
+        if keep_above {
+            axis.of(&corner.vertex) >= plane
+        } else {
+            axis.of(&corner.vertex) <= plane
+        }
+    };
+
+    let mut clipped = Vec::with_capacity(polygon.len() + 1);
+    for (i, current) in polygon.iter().enumerate() {
+        let next = &polygon[(i + 1) % polygon.len()];
+
+        match (inside(current), inside(next)) {
+            (true, true) => clipped.push(*current),
+            // a corner lying on the plane already is the intersection, interpolating
+            // towards it again would only add a slightly different copy of it
+            (true, false) => {
+                clipped.push(*current);
+                if axis.of(&current.vertex) != plane {
+                    clipped.push(intersect(current, next, axis, plane));
+                }
+            },
+            (false, true) => if axis.of(&next.vertex) != plane {
+                clipped.push(intersect(current, next, axis, plane));
+            },
+            (false, false) => {},
+        }
+    }
+
+    clipped
+}
+
+/// Clips a convex polygon to the slab `min <= axis <= max`
+fn clip_to_slab(polygon: &[ClipVertex], axis: Axis, min: f32, max: f32) -> Vec<ClipVertex> {
+    clip_half_plane(&clip_half_plane(polygon, axis, min, true), axis, max, false)
+}
+
+/// Cuts a convex polygon along the grid planes `origin + n * cell_size` of one axis,
+/// returning every piece that still has an area together with the cell it lies in
+fn split_along(polygon: &[ClipVertex], axis: Axis, origin: f32, cell_size: f32) -> Vec<(i32, Vec<ClipVertex>)> {
+    let Some((min, max)) = polygon.iter()
+        .map(|corner| ((axis.of(&corner.vertex) - origin) / cell_size).floor() as i32)
+        .minmax()
+        .into_option() else {
+        return Vec::new();
+    };
+
+    (min..=max)
+        .map(|cell| (cell, clip_to_slab(polygon, axis, origin + cell as f32 * cell_size, origin + (cell + 1) as f32 * cell_size)))
+        .filter(|(_, piece)| piece.len() >= 3)
+        .collect()
+}
+
+fn is_degenerate(a: &Vertex, b: &Vertex, c: &Vertex) -> bool {
+    let a = Vec3::new(a.x, a.y, a.z);
+    let b = Vec3::new(b.x, b.y, b.z);
+    let c = Vec3::new(c.x, c.y, c.z);
+
+    // squaring the length would underflow to zero for tiny triangles that still have an area
+    let normal = (b - a).cross(c - a);
+    normal.is_nan() || normal == Vec3::ZERO
+}
+
+/// Adds a clipped convex piece of a triangle to the chunk at `chunk_pos`
+fn add_piece(chunks: &mut HashMap<ChunkPos, ChunkData>, chunk_pos: ChunkPos, piece: &[ClipVertex], index: &Index) {
+    let chunk = chunks.entry(chunk_pos).or_insert_with(ChunkData::new);
+
+    // the clipped piece is convex, so a fan around the first corner covers it
+    for i in 1..piece.len() - 1 {
+        if is_degenerate(&piece[0].vertex, &piece[i].vertex, &piece[i + 1].vertex) {
+            continue;
+        }
+
+        let local_index = Index {
+            x: chunk.local_index(&piece[0]),
+            y: chunk.local_index(&piece[i]),
+            z: chunk.local_index(&piece[i + 1]),
+            ..*index
+        };
+        chunk.indices.push(local_index);
+    }
+}
+
+/// Cuts a triangle along the chunk grid planes and adds each piece to the chunk it lies in
+fn clip_triangle(chunks: &mut HashMap<ChunkPos, ChunkData>, vertices: &[Vertex], index: &Index, grid: &ChunkGrid) {
+    let triangle: Vec<ClipVertex> = [index.x, index.y, index.z].iter()
+        .map(|global_idx| ClipVertex { vertex: vertices[*global_idx as usize], global_idx: Some(*global_idx) })
+        .collect();
+
+    for (chunk_x, strip) in split_along(&triangle, Axis::X, grid.origin.x, grid.cell_size) {
+        for (chunk_z, piece) in split_along(&strip, Axis::Z, grid.origin.z, grid.cell_size) {
+            let Some(cell_height) = grid.cell_height else {
+                add_piece(chunks, ChunkPos { x: chunk_x, y: 0, z: chunk_z }, &piece, index);
+                continue;
+            };
+
+            for (chunk_y, layer) in split_along(&piece, Axis::Y, grid.origin.y, cell_height) {
+                add_piece(chunks, ChunkPos { x: chunk_x, y: chunk_y, z: chunk_z }, &layer, index);
+            }
+        }
+    }
+}
+
+/// What the cleanup pass removed from a mesh
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CleanupReport {
+    /// Vertices merged into another vertex within the tolerance
+    pub welded_vertices: usize,
+    /// Vertices no triangle used any more
+    pub unused_vertices: usize,
+    /// Triangles with no area
+    pub degenerate_triangles: usize,
+    /// Triangles using the same three vertices as an earlier one, wound the same way
+    pub duplicate_triangles: usize,
+}
+
+impl fmt::Display for CleanupReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} welded and {} unused vertices, {} degenerate and {} duplicate triangles",
+            self.welded_vertices, self.unused_vertices, self.degenerate_triangles, self.duplicate_triangles)
+    }
+}
+
+type WeldCell = (i64, i64, i64);
+
+fn weld_cell(vertex: &Vertex, tolerance: f32) -> WeldCell {
+    (
+        (vertex.x / tolerance).floor() as i64,
+        (vertex.y / tolerance).floor() as i64,
+        (vertex.z / tolerance).floor() as i64,
+    )
+}
+
+/// Maps every vertex to the first vertex within `tolerance` of it, returning the kept vertices and the mapping
+fn weld(vertices: &[Vertex], tolerance: f32) -> (Vec<Vertex>, Vec<u32>) {
+    let mut welded = Vec::new();
+    let mut remap = Vec::with_capacity(vertices.len());
+
+    if tolerance <= 0.0 {
+        // without a tolerance only bit-identical vertices are merged
+        let mut seen: HashMap<[u32; 3], u32> = HashMap::new();
+        for vertex in vertices {
+            let welded_idx = *seen.entry(vertex.bits()).or_insert_with(|| {
+                welded.push(*vertex);
+                welded.len() as u32 - 1
+            });
+            remap.push(welded_idx);
+        }
+
+        return (welded, remap);
+    }
+
+    // cells as wide as the tolerance, so any match is in the same or a neighbouring cell
+    let mut grid: HashMap<WeldCell, Vec<u32>> = HashMap::new();
+    for vertex in vertices {
+        let cell = weld_cell(vertex, tolerance);
+        let position = Vec3::new(vertex.x, vertex.y, vertex.z);
+
+        let existing = (-1..=1).cartesian_product(-1..=1).cartesian_product(-1..=1)
+            .filter_map(|((dx, dy), dz)| grid.get(&(cell.0 + dx, cell.1 + dy, cell.2 + dz)))
+            .flatten()
+            .copied()
+            .filter(|welded_idx| {
+                let other = welded[*welded_idx as usize];
+                position.distance(Vec3::new(other.x, other.y, other.z)) <= tolerance
+            })
+            .min();
+
+        let welded_idx = existing.unwrap_or_else(|| {
+            welded.push(*vertex);
+            let welded_idx = welded.len() as u32 - 1;
+            grid.entry(cell).or_default().push(welded_idx);
+            welded_idx
         });
+        remap.push(welded_idx);
+    }
+
+    (welded, remap)
+}
+
+/// Welds vertices within `tolerance`, then drops degenerate, duplicate and unused geometry.
+/// Expects indices that passed [`validate`].
+fn clean(vertices: &[Vertex], indices: &[Index], tolerance: f32) -> (Vec<Vertex>, Vec<Index>, CleanupReport) {
+    let mut report = CleanupReport::default();
+
+    let (welded, remap) = weld(vertices, tolerance);
+    report.welded_vertices = vertices.len() - welded.len();
+
+    let mut seen_triangles = HashSet::new();
+    let mut triangles = Vec::with_capacity(indices.len());
+    for index in indices {
+        let triangle = [remap[index.x as usize], remap[index.y as usize], remap[index.z as usize]];
+
+        if triangle[0] == triangle[1] || triangle[1] == triangle[2] || triangle[0] == triangle[2]
+            || is_degenerate(&welded[triangle[0] as usize], &welded[triangle[1] as usize], &welded[triangle[2] as usize]) {
+            report.degenerate_triangles += 1;
+            continue;
+        }
+
+        // rotated so the smallest index comes first, which keeps the winding - a flipped copy is the other side of a thin wall
+        let first = (0..3).min_by_key(|corner| triangle[*corner]).unwrap();
+        let key = [triangle[first], triangle[(first + 1) % 3], triangle[(first + 2) % 3]];
+        if !seen_triangles.insert(key) {
+            report.duplicate_triangles += 1;
+            continue;
+        }
+
+        triangles.push((triangle, index));
+    }
+
+    // compact the vertex buffer down to what the remaining triangles use
+    let mut compacted_idx: HashMap<u32, u32> = HashMap::new();
+    let mut compacted = Vec::new();
+    let cleaned_indices = triangles.into_iter()
+        .map(|(triangle, index)| {
+            let [x, y, z] = triangle.map(|welded_idx| *compacted_idx.entry(welded_idx).or_insert_with(|| {
+                compacted.push(welded[welded_idx as usize]);
+                compacted.len() as u32 - 1
+            }));
+            Index { x, y, z, ..*index }
+        })
+        .collect();
+    report.unused_vertices = welded.len() - compacted.len();
+
+    (compacted, cleaned_indices, report)
+}
+
+/// Whether the chunk position of `vertex` fits in an `i32`, which also rules out NaN and infinite positions
+fn in_grid_range(grid: &ChunkGrid, vertex: &Vertex) -> bool {
+    let range = i32::MIN as f32..i32::MAX as f32;
+    let cell = (vertex.to_vec3() - grid.origin) / grid.cell_size;
+
+    range.contains(&cell.x)
+        && range.contains(&cell.z)
+        && grid.cell_height.map_or(vertex.y.is_finite(), |cell_height| range.contains(&((vertex.y - grid.origin.y) / cell_height)))
+}
+
+/// Makes sure every index can be looked up, so splitting itself can't panic
+fn validate(vertices: &[Vertex], indices: &[Index]) -> Result<(), SplitError> {
+    if u32::try_from(vertices.len()).is_err() {
+        return Err(SplitError::TooManyVertices(vertices.len()));
+    }
+
+    for index in indices {
+        for global_idx in [index.x, index.y, index.z] {
+            if global_idx as usize >= vertices.len() {
+                return Err(SplitError::IndexOutOfRange { index: global_idx, vertex_count: vertices.len() });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Vertex and index buffers of every chunk, with indices local to the chunk
+type SplitChunks = HashMap<ChunkPos, (Vec<Vertex>, Vec<Index>)>;
+
+fn split_mesh(vertices: Vec<Vertex>, indices: Vec<Index>, settings: &SplitSettings) -> Result<SplitChunks, SplitError> {
+    validate(&vertices, &indices)?;
+
+    let grid = settings.grid();
+    let mut chunks: HashMap<ChunkPos, ChunkData> = HashMap::new();
+
+    // farther vertices would get saturated chunk positions, and their triangles clipped at the wrong planes.
+    // Vertices no triangle uses are never split, so a stray one doesn't cost the whole mesh its collider.
+    if let Some(global_idx) = indices.iter()
+        .flat_map(|index| [index.x, index.y, index.z])
+        .find(|global_idx| !in_grid_range(&grid, &vertices[*global_idx as usize])) {
+        return Err(SplitError::VertexOutOfGrid(global_idx));
+    }
+
+    // assign vertices to chunks
+    // unused vertices out of range have no chunk, and would end up in some chunk's collider if given a saturated one
+    for (global_idx, vertex) in vertices.iter().enumerate().filter(|(_, vertex)| in_grid_range(&grid, vertex)) {
+        let chunk_pos = grid.local_to_chunk(vertex.to_vec3());
+        let entry = chunks.entry(chunk_pos).or_insert_with(ChunkData::new);
 
         entry.vertices.push(*vertex);
-        entry.global_local_index_map.insert(global_idx.try_into().unwrap(), entry.vertices.len() as u32 - 1);
+        // `validate` made sure every global index fits in a u32
+        entry.global_local_index_map.insert(global_idx as u32, entry.vertices.len() as u32 - 1);
     }
 
-    // assign indices to chunks 
+    // assign indices to chunks, every piece keeping the source of the triangle it came from
     for index in indices {
         let vert_chunks = [
-            ChunkPos::from_vertex(&vertices[index.x as usize], &chunk_size),
-            ChunkPos::from_vertex(&vertices[index.y as usize], &chunk_size),
-            ChunkPos::from_vertex(&vertices[index.z as usize], &chunk_size),
+            grid.local_to_chunk(vertices[index.x as usize].to_vec3()),
+            grid.local_to_chunk(vertices[index.y as usize].to_vec3()),
+            grid.local_to_chunk(vertices[index.z as usize].to_vec3()),
         ];
 
         // if all are in the same chunk, add triangle to it 
@@ -112,9 +604,14 @@ fn split_mesh(vertices: Vec<Vertex>, indices: Vec<Index>, chunk_size: f32) -> Ha
                     x: *chunk.global_local_index_map.get(&index.x).unwrap(),
                     y: *chunk.global_local_index_map.get(&index.y).unwrap(),
                     z: *chunk.global_local_index_map.get(&index.z).unwrap(),
+                    ..index
                 });
             }
         }
+        // the triangle is in multiple chunks - cut it along the chunk borders
+        else if settings.boundary_mode == BoundaryMode::Clip {
+            clip_triangle(&mut chunks, &vertices, &index, &grid);
+        }
         // the triangle is in multiple chunks - add it to all relative chunks 
         else {
             // for every unique chunk
@@ -143,70 +640,1382 @@ fn split_mesh(vertices: Vec<Vertex>, indices: Vec<Index>, chunk_size: f32) -> Ha
                         }
                     }
 
-                    if added_new_verts || ChunkPos::from_vertex(&vertices[index.x as usize], &chunk_size) == *pos {
-                        chunk.indices.push(Index {x: local_index[0], y: local_index[1], z: local_index[2]});
+                    if added_new_verts || grid.local_to_chunk(vertices[index.x as usize].to_vec3()) == *pos {
+                        chunk.indices.push(Index {x: local_index[0], y: local_index[1], z: local_index[2], ..index});
                     }
                 }
             }
         }
     }
 
-    chunks.into_iter()
+    Ok(chunks.into_iter()
         .map(|(chunk_pos, chunk_data)| (chunk_pos, (chunk_data.vertices, chunk_data.indices)))
-        .collect()
+        .collect())
 }
 
 /// Adapted from https://github.com/dimforge/bevy_rapier/blob/master/src/geometry/collider_impl.rs#L738
 /// Returns vertex and index buffers (in that order)
-fn to_vertices(mesh: &Mesh) -> Option<(Vec<Vertex>, Vec<Index>)> {
-    let vertices = mesh.attribute(Mesh::ATTRIBUTE_POSITION)?;
-    let indices = mesh.indices()?;
+fn to_vertices(mesh: &Mesh) -> Result<(Vec<Vertex>, Vec<Index>), SplitError> {
+    let topology = mesh.primitive_topology();
+    if !matches!(topology, PrimitiveTopology::TriangleList | PrimitiveTopology::TriangleStrip) {
+        return Err(SplitError::UnsupportedTopology(topology));
+    }
+
+    let vertices = mesh.attribute(Mesh::ATTRIBUTE_POSITION).ok_or(SplitError::MissingPositions)?;
 
     let vtx: Vec<_> = match vertices {
-        VertexAttributeValues::Float32(vtx) => Some(
-            vtx.chunks(3)
+        VertexAttributeValues::Float32(vtx) => Ok(
+            vtx.chunks_exact(3)
                 .map(|v| Vertex::from([v[0] as f32, v[1] as f32, v[2] as f32]))
                 .collect(),
         ),
-        VertexAttributeValues::Float32x3(vtx) => Some(
+        VertexAttributeValues::Float32x3(vtx) => Ok(
             vtx.iter()
                 .map(|v| Vertex::from([v[0] as f32, v[1] as f32, v[2] as f32]))
                 .collect(),
         ),
-        _=> None,
+        other => Err(SplitError::UnsupportedPositionFormat(VertexFormat::from(other))),
     }?;
 
-    let idx = match indices {
-        Indices::U16(idx) => idx.chunks_exact(3)
-            .map(|i| Index::from([i[0] as u32, i[1] as u32, i[2] as u32]))
+    let raw_idx: Vec<u32> = match mesh.indices() {
+        Some(Indices::U16(idx)) => idx.iter().map(|i| *i as u32).collect(),
+        Some(Indices::U32(idx)) => idx.clone(),
+        // non-indexed meshes use every vertex once, in order
+        None => {
+            let vertex_count = u32::try_from(vtx.len()).map_err(|_| SplitError::TooManyVertices(vtx.len()))?;
+            (0..vertex_count).collect()
+        }
+    };
+
+    if topology == PrimitiveTopology::TriangleList && !raw_idx.len().is_multiple_of(3) {
+        return Err(SplitError::IncompleteTriangle(raw_idx.len()));
+    }
+
+    let mut idx: Vec<Index> = match topology {
+        PrimitiveTopology::TriangleStrip => raw_idx.windows(3)
+            .enumerate()
+            // every other triangle of a strip is wound the other way round
+            .map(|(n, i)| if n % 2 == 0 {
+                Index::from([i[0], i[1], i[2]])
+            } else {
+                Index::from([i[1], i[0], i[2]])
+            })
             .collect(),
-        Indices::U32(idx) => idx.chunks_exact(3).map(|i| Index::from([i[0], i[1], i[2]])).collect(),
+        _ => raw_idx.chunks_exact(3).map(|i| Index::from([i[0], i[1], i[2]])).collect(),
     };
+    for (source, index) in idx.iter_mut().enumerate() {
+        index.source = source as u32;
+    }
+
+    // strips are joined by triangles without an area, which shouldn't depend on cleanup being on to be dropped
+    if topology == PrimitiveTopology::TriangleStrip {
+        idx.retain(|index| match [index.x, index.y, index.z].map(|i| vtx.get(i as usize)) {
+            [Some(a), Some(b), Some(c)] => !is_degenerate(a, b, c),
+            // left for `validate` to report
+            _ => true,
+        });
+    }
+
+    // a triangle is made of whatever its first vertex is made of
+    if let Some(surfaces) = collider_surface::vertex_surfaces(mesh) {
+        for index in &mut idx {
+            index.surface = SurfaceId(surfaces.get(index.x as usize).copied().unwrap_or_default());
+        }
+    }
 
-    Some((vtx, idx))
+    Ok((vtx, idx))
 }
 
-fn split_bevy_mesh(mesh: &Mesh, chunk_size: f32) -> HashMap<ChunkPos, (Vec<Vertex>, Vec<Index>)> {
-    let mesh = to_vertices(mesh).unwrap_or((vec![], vec![]));
-    split_mesh(mesh.0, mesh.1, chunk_size)
+/// Vertices and triangles ready to split, and what the cleanup pass removed to get there
+type PreparedMesh = (Vec<Vertex>, Vec<Index>, Option<CleanupReport>);
+
+/// Reads the triangles `split_mesh` gets out of `mesh`, running the cleanup pass first if `settings` ask for it
+fn prepare_mesh(mesh: &Mesh, settings: &SplitSettings) -> Result<PreparedMesh, SplitError> {
+    let (vertices, indices) = to_vertices(mesh)?;
+
+    match settings.cleanup_tolerance {
+        Some(tolerance) => {
+            validate(&vertices, &indices)?;
+            let (vertices, indices, report) = clean(&vertices, &indices, tolerance);
+            if report != CleanupReport::default() {
+                info!("Mesh cleanup removed {}", report);
+            }
+            Ok((vertices, indices, Some(report)))
+        },
+        None => Ok((vertices, indices, None)),
+    }
 }
 
-pub fn split_subcolliders(mesh: &Mesh, chunk_size: f32) -> Vec<(ChunkPos, Collider)> {
-    let meshlets: Vec<(ChunkPos, (Vec<Vertex>, Vec<Index>))> = split_bevy_mesh(mesh, chunk_size).into_iter()
-        .filter(|(_, (_, indices))| indices.len() > 0)
-        .collect();
+fn split_bevy_mesh(mesh: &Mesh, settings: &SplitSettings) -> Result<SplitChunks, SplitError> {
+    let (vertices, indices, _) = prepare_mesh(mesh, settings)?;
+    split_mesh(vertices, indices, settings)
+}
 
-    let mut subcolliders = Vec::new();
-    for meshlet in meshlets {
-        let vertices: Vec<Vec3> = meshlet.1.0.into_iter().map(|vert|{
-            vert
-        }).map(|vert| Vec3::new(vert.x, vert.y, vert.z)).collect();
-        let indices = meshlet.1.1.into_iter().map(|idx| {
-            idx
-        }).map(|idx| [idx.x, idx.y, idx.z]).collect();
+/// Runs only the cleanup pass of [`try_split_meshlets`] on `mesh`, to see how much it would remove
+pub fn try_cleanup_report(mesh: &Mesh, tolerance: f32) -> Result<CleanupReport, SplitError> {
+    let (vertices, indices) = to_vertices(mesh)?;
+    validate(&vertices, &indices)?;
+
+    Ok(clean(&vertices, &indices, tolerance).2)
+}
+
+/// Picks the values of `vertices` out of `values`, in that order
+fn gather(values: &VertexAttributeValues, vertices: &[u32]) -> VertexAttributeValues {
+    macro_rules! gather {
+        ($($format:ident),*) => {
+            match values {
+                $(VertexAttributeValues::$format(values) => VertexAttributeValues::$format(vertices.iter().map(|i| values[*i as usize]).collect()),)*
+            }
+        };
+    }
+
+    gather!(Float32, Sint32, Uint32, Float32x2, Sint32x2, Uint32x2, Float32x3, Sint32x3, Uint32x3, Float32x4, Sint32x4, Uint32x4,
+        Sint16x2, Snorm16x2, Uint16x2, Unorm16x2, Sint16x4, Snorm16x4, Uint16x4, Unorm16x4,
+        Sint8x2, Snorm8x2, Uint8x2, Unorm8x2, Sint8x4, Snorm8x4, Uint8x4, Unorm8x4)
+}
 
-        subcolliders.push((meshlet.0, Collider::trimesh(vertices, indices)));
+/// The triangles of a render mesh that fall into a single chunk
+#[derive(Default)]
+struct RenderChunkData {
+    // source vertex of every chunk vertex
+    source_vertices: Vec<u32>,
+    global_local_index_map: HashMap<u32, u32>,
+    indices: Vec<u32>,
+}
+
+/// A copy of `mesh` with only what splitting it into colliders reads: positions, [`collider_surface::ATTRIBUTE_SURFACE`] and indices
+pub fn collider_mesh(mesh: &Mesh) -> Mesh {
+    let mut collider_mesh = Mesh::new(mesh.primitive_topology(), RenderAssetUsages::MAIN_WORLD);
+    for attribute in [Mesh::ATTRIBUTE_POSITION, collider_surface::ATTRIBUTE_SURFACE] {
+        if let Some(values) = mesh.attribute(attribute.id) {
+            collider_mesh.insert_attribute(attribute, values.clone());
+        }
+    }
+    if let Some(indices) = mesh.indices() {
+        collider_mesh.insert_indices(indices.clone());
+    }
+    collider_mesh
+}
+
+/// An empty copy of `mesh`, so chunks keep every attribute and setting the source has, custom ones included
+pub fn empty_render_mesh(mesh: &Mesh) -> Mesh {
+    let mut template = mesh.clone();
+    template.remove_indices();
+    for (_, values) in template.attributes_mut() {
+        *values = gather(values, &[]);
+    }
+    template
+}
+
+/// Splits a render mesh into one mesh per chunk, keeping every vertex attribute (normals, UVs, tangents, ...).
+/// Triangles aren't clipped like they are for colliders, since that would mean interpolating every attribute -
+/// instead each one goes whole to the chunk its centre lies in, so chunk meshes overhang their cell a little.
+pub fn try_split_render_mesh(mesh: &Mesh, grid: &ChunkGrid) -> Result<Vec<(ChunkPos, Mesh)>, SplitError> {
+    let topology = mesh.primitive_topology();
+    if topology != PrimitiveTopology::TriangleList {
+        return Err(SplitError::UnsupportedRenderTopology(topology));
+    }
+
+    let (vertices, indices) = to_vertices(mesh)?;
+    validate(&vertices, &indices)?;
+    for (attribute, values) in mesh.attributes() {
+        if values.len() != vertices.len() {
+            return Err(SplitError::AttributeLengthMismatch { attribute, len: values.len(), vertex_count: vertices.len() });
+        }
+    }
+
+    let mut chunks: HashMap<ChunkPos, RenderChunkData> = HashMap::new();
+    for index in &indices {
+        let centre = (vertices[index.x as usize].to_vec3() + vertices[index.y as usize].to_vec3() + vertices[index.z as usize].to_vec3()) / 3.0;
+        let chunk = chunks.entry(grid.local_to_chunk(centre)).or_default();
+
+        for global_idx in [index.x, index.y, index.z] {
+            let local_idx = *chunk.global_local_index_map.entry(global_idx).or_insert_with(|| {
+                chunk.source_vertices.push(global_idx);
+                chunk.source_vertices.len() as u32 - 1
+            });
+            chunk.indices.push(local_idx);
+        }
+    }
+
+    let template = empty_render_mesh(mesh);
+
+    Ok(chunks.into_iter()
+        .map(|(chunk_pos, chunk)| {
+            let mut chunk_mesh = template.clone();
+            for (id, values) in chunk_mesh.attributes_mut() {
+                *values = gather(mesh.attribute(id).unwrap(), &chunk.source_vertices);
+            }
+            chunk_mesh.insert_indices(Indices::U32(chunk.indices));
+            (chunk_pos, chunk_mesh)
+        })
+        .collect())
+}
+
+/// The part of a mesh that falls into a single chunk
+#[derive(Clone, Debug, PartialEq)]
+pub struct Meshlet {
+    pub chunk_pos: ChunkPos,
+    pub vertices: Vec<Vec3>,
+    pub indices: Vec<[u32; 3]>,
+    /// Surface of every triangle in `indices`
+    pub surfaces: Vec<SurfaceId>,
+    /// Decimated versions of the chunk, finest first
+    pub lods: Vec<MeshletLod>,
+    /// The chunk resampled on a regular grid, used instead of the full detail trimesh.
+    /// Only chunks made of a single surface get one, since a heightfield has nowhere to store more.
+    pub heightfield: Option<Heightfield>,
+}
+
+/// A decimated copy of a [`Meshlet`]'s triangles
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MeshletLod {
+    pub vertices: Vec<Vec3>,
+    pub indices: Vec<[u32; 3]>,
+    /// Surface of every triangle in `indices`, taken from the closest full detail triangle
+    pub surfaces: Vec<SurfaceId>,
+}
+
+impl MeshletLod {
+    /// Adds the triangles of another mesh in the same chunk
+    fn append(&mut self, vertices: &[Vec3], indices: &[[u32; 3]], surfaces: &[SurfaceId]) {
+        let offset = self.vertices.len() as u32;
+        self.vertices.extend_from_slice(vertices);
+        self.indices.extend(indices.iter().map(|triangle| triangle.map(|i| i + offset)));
+        self.surfaces.extend_from_slice(surfaces);
+    }
+}
+
+/// Collider of every level of detail of a chunk, full detail first, each with the surfaces of its triangles
+pub type ChunkColliders = Vec<(Collider, SurfaceLookup)>;
+
+impl Meshlet {
+    pub fn to_collider(&self) -> Collider {
+        match &self.heightfield {
+            Some(heightfield) => heightfield.to_collider(),
+            None => Collider::trimesh(self.vertices.clone(), self.indices.clone()),
+        }
+    }
+
+    /// One collider per level of detail, full detail first
+    pub fn to_lod_colliders(&self) -> ChunkColliders {
+        std::iter::once((self.to_collider(), SurfaceLookup::new(&self.surfaces)))
+            .chain(self.lods.iter().map(|lod| (Collider::trimesh(lod.vertices.clone(), lod.indices.clone()), SurfaceLookup::new(&lod.surfaces))))
+            .collect()
+    }
+
+    fn build_lods(&mut self, levels: &[LodLevel]) {
+        let (mut vertices, mut indices) = (self.vertices.clone(), self.indices.clone());
+
+        for level in levels {
+            (vertices, indices) = collider_decimator::decimate(&vertices, &indices, level.target_ratio, level.max_error);
+            // a trimesh needs at least one triangle
+            if indices.is_empty() {
+                break;
+            }
+
+            self.lods.push(MeshletLod {
+                surfaces: collider_surface::transfer(&self.vertices, &self.indices, &self.surfaces, &vertices, &indices),
+                vertices: vertices.clone(),
+                indices: indices.clone(),
+            });
+        }
     }
+}
+
+/// Turns the triangles of a single chunk into a meshlet, with its levels of detail and heightfield
+fn build_meshlet(chunk_pos: ChunkPos, vertices: Vec<Vertex>, indices: Vec<Index>, settings: &SplitSettings) -> Meshlet {
+    let mut meshlet = Meshlet {
+        chunk_pos,
+        vertices: vertices.into_iter().map(|vert| Vec3::new(vert.x, vert.y, vert.z)).collect(),
+        surfaces: indices.iter().map(|idx| idx.surface).collect(),
+        indices: indices.into_iter().map(|idx| [idx.x, idx.y, idx.z]).collect(),
+        lods: Vec::new(),
+        heightfield: None,
+    };
+    meshlet.build_lods(settings.lods);
+    meshlet.heightfield = fit_heightfield(&meshlet, settings);
+    meshlet
+}
+
+fn fit_heightfield(meshlet: &Meshlet, settings: &SplitSettings) -> Option<Heightfield> {
+    settings.heightfield_tolerance
+        .filter(|_| collider_surface::uniform(&meshlet.surfaces).is_some())
+        .and_then(|max_error| collider_heightfield::fit(&meshlet.vertices, &meshlet.indices, max_error))
+}
+
+/// Splits a mesh into chunks, dropping chunks that ended up without triangles
+pub fn try_split_meshlets(mesh: &Mesh, settings: &SplitSettings) -> Result<Vec<Meshlet>, SplitError> {
+    Ok(build_meshlets(split_bevy_mesh(mesh, settings)?, settings))
+}
+
+fn build_meshlets(chunks: SplitChunks, settings: &SplitSettings) -> Vec<Meshlet> {
+    chunks.into_iter()
+        .filter(|(_, (_, indices))| indices.len() > 0)
+        .map(|(chunk_pos, (vertices, indices))| build_meshlet(chunk_pos, vertices, indices, settings))
+        .collect()
+}
+
+/// Triangles and extent of a single chunk a split kept
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ChunkReport {
+    pub chunk_pos: ChunkPos,
+    /// Triangles in the chunk, before levels of detail are built
+    pub triangles: usize,
+    /// Lowest corner of the chunk's triangles, in the mesh's local space
+    pub min: Vec3,
+    /// Highest corner of the chunk's triangles
+    pub max: Vec3,
+}
+
+/// What splitting a mesh did with its triangles, see [`try_split_meshlets_with_report`]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SplitReport {
+    /// Triangles handed to the split, after the cleanup pass
+    pub source_triangles: usize,
+    /// Triangles in all kept chunks, counting every clipped piece
+    pub emitted_triangles: usize,
+    /// Extra chunks source triangles went into because they straddle a chunk border
+    pub duplicated_triangles: usize,
+    /// Chunks that only got vertices and no triangles
+    pub empty_chunks_dropped: usize,
+    /// What the cleanup pass removed, if `SplitSettings::cleanup_tolerance` ran it
+    pub cleanup: Option<CleanupReport>,
+    /// Every kept chunk, sorted by position
+    pub chunks: Vec<ChunkReport>,
+    /// Positions in the mesh's own triangle list of source triangles that ended up in no chunk at all, e.g. slivers
+    /// whose clipped pieces were all too thin to keep. Anything else in here is a bug in the split.
+    pub missing_triangles: Vec<usize>,
+}
+
+impl SplitReport {
+    /// `sources` holds the source of every triangle handed to the split
+    fn new(chunks: &SplitChunks, sources: &[u32], cleanup: Option<CleanupReport>) -> Self {
+        let mut report = SplitReport { source_triangles: sources.len(), cleanup, ..SplitReport::default() };
+        // chunks every source triangle went into
+        let mut source_chunks: HashMap<u32, usize> = sources.iter().map(|source| (*source, 0)).collect();
+
+        for (chunk_pos, (vertices, indices)) in chunks {
+            if indices.is_empty() {
+                report.empty_chunks_dropped += 1;
+                continue;
+            }
+
+            let (min, max) = indices.iter()
+                .flat_map(|index| [index.x, index.y, index.z])
+                .map(|local_index| vertices[local_index as usize].to_vec3())
+                .fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), vertex| (min.min(vertex), max.max(vertex)));
+            report.chunks.push(ChunkReport { chunk_pos: *chunk_pos, triangles: indices.len(), min, max });
+            report.emitted_triangles += indices.len();
 
-    subcolliders
+            for source in indices.iter().map(|index| index.source).unique() {
+                *source_chunks.entry(source).or_default() += 1;
+            }
+        }
+
+        report.chunks.sort_by_key(|chunk| (chunk.chunk_pos.x, chunk.chunk_pos.y, chunk.chunk_pos.z));
+        report.duplicated_triangles = source_chunks.values().map(|count| count.saturating_sub(1)).sum();
+        report.missing_triangles = source_chunks.iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(source, _)| *source as usize)
+            .sorted()
+            .collect();
+        report
+    }
+
+    /// Whether every source triangle made it into at least one chunk
+    pub fn is_valid(&self) -> bool {
+        self.missing_triangles.is_empty()
+    }
+
+    /// Number of chunks per power of two of triangles: entry `i` counts the chunks with `2^i` up to `2^(i + 1) - 1` triangles
+    pub fn histogram(&self) -> Vec<usize> {
+        let mut histogram = Vec::new();
+        for chunk in &self.chunks {
+            let bucket = chunk.triangles.ilog2() as usize;
+            if histogram.len() <= bucket {
+                histogram.resize(bucket + 1, 0);
+            }
+            histogram[bucket] += 1;
+        }
+        histogram
+    }
+}
+
+impl fmt::Display for SplitReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} source triangles became {} in {} chunks ({} duplicated across borders, {} empty chunks dropped, {} missing)",
+            self.source_triangles, self.emitted_triangles, self.chunks.len(),
+            self.duplicated_triangles, self.empty_chunks_dropped, self.missing_triangles.len())?;
+        if let Some(cleanup) = &self.cleanup {
+            write!(f, ", cleanup removed {}", cleanup)?;
+        }
+        Ok(())
+    }
+}
+
+/// Same as [`try_split_meshlets`], but also reports where the triangles went and checks that none got lost
+pub fn try_split_meshlets_with_report(mesh: &Mesh, settings: &SplitSettings) -> Result<(Vec<Meshlet>, SplitReport), SplitError> {
+    let (vertices, indices, cleanup) = prepare_mesh(mesh, settings)?;
+    let sources: Vec<u32> = indices.iter().map(|index| index.source).collect();
+    let chunks = split_mesh(vertices, indices, settings)?;
+
+    let report = SplitReport::new(&chunks, &sources, cleanup);
+    if !report.is_valid() {
+        warn!("{} source triangles ended up in no chunk: {:?}", report.missing_triangles.len(), report.missing_triangles);
+    }
+
+    Ok((build_meshlets(chunks, settings), report))
+}
+
+/// Triangles replacing part of an already split mesh, in the mesh's local space
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegionEdit {
+    /// Lowest corner of the edited box. Every triangle whose centre lies in the box is removed.
+    pub min: Vec3,
+    /// Highest corner of the edited box
+    pub max: Vec3,
+    /// Triangles added in place of the removed ones. They may reach out of the box.
+    pub vertices: Vec<Vec3>,
+    pub indices: Vec<[u32; 3]>,
+    /// Surface of every triangle in `indices`
+    pub surfaces: Vec<SurfaceId>,
+}
+
+/// Rebuilds only the chunks of `meshlets` an edit touches, instead of splitting the whole mesh again.
+/// `meshlets` are keyed by the leaves of `tree`, which stays as it is - new geometry outside its leaves
+/// gets chunks of the grid's size. Returns every rebuilt chunk, or `None` for chunks the edit left without triangles.
+pub fn try_resplit_region(meshlets: &HashMap<ChunkPos, Meshlet>, tree: &ChunkTree, edit: &RegionEdit, settings: &SplitSettings) -> Result<Vec<(ChunkPos, Option<Meshlet>)>, SplitError> {
+    let vertices: Vec<Vertex> = edit.vertices.iter().map(|vertex| Vertex::from(*vertex)).collect();
+    let indices: Vec<Index> = edit.indices.iter()
+        .enumerate()
+        .map(|(i, triangle)| Index { surface: edit.surfaces.get(i).copied().unwrap_or_default(), source: i as u32, ..Index::from(*triangle) })
+        .collect();
+    validate(&vertices, &indices)?;
+
+    // the new triangles go through the same pipeline as a whole mesh would
+    let split = match settings.cleanup_tolerance {
+        Some(tolerance) => {
+            let (vertices, indices, _) = clean(&vertices, &indices, tolerance);
+            split_mesh(vertices, indices, settings)?
+        },
+        None => split_mesh(vertices, indices, settings)?,
+    };
+
+    // gather the pieces by leaf, skipping the empty chunks vertices on a chunk border leave behind
+    let mut added: SplitChunks = HashMap::new();
+    for (chunk_pos, (vertices, indices)) in split.into_iter().filter(|(_, (_, indices))| !indices.is_empty()) {
+        let (leaf_vertices, leaf_indices) = added.entry(tree.leaf(chunk_pos)).or_default();
+        let offset = leaf_vertices.len() as u32;
+        leaf_vertices.extend(vertices);
+        leaf_indices.extend(indices.into_iter().map(|index| Index { x: index.x + offset, y: index.y + offset, z: index.z + offset, ..index }));
+    }
+
+    let grid = settings.grid();
+    let (min_chunk, max_chunk) = (grid.local_to_chunk(edit.min), grid.local_to_chunk(edit.max));
+    let mut affected: HashSet<ChunkPos> = added.keys().copied().collect();
+    affected.extend(meshlets.keys().copied().filter(|leaf| {
+        let last = tree.last_chunk(*leaf);
+        leaf.x <= max_chunk.x && last.x >= min_chunk.x
+            && (min_chunk.y..=max_chunk.y).contains(&leaf.y)
+            && leaf.z <= max_chunk.z && last.z >= min_chunk.z
+    }));
+
+    Ok(affected.into_iter()
+        .map(|chunk_pos| {
+            let mut chunk = ChunkData::new();
+
+            // the old triangles outside the box already lie in this chunk, so they only need carrying over
+            if let Some(meshlet) = meshlets.get(&chunk_pos) {
+                for (triangle, surface) in meshlet.indices.iter().zip(&meshlet.surfaces) {
+                    let centre = triangle.iter().map(|i| meshlet.vertices[*i as usize]).sum::<Vec3>() / 3.0;
+                    if centre.cmpge(edit.min).all() && centre.cmple(edit.max).all() {
+                        continue;
+                    }
+
+                    let [x, y, z] = triangle.map(|global_idx| chunk.local_index(&ClipVertex {
+                        vertex: Vertex::from(meshlet.vertices[global_idx as usize]),
+                        global_idx: Some(global_idx),
+                    }));
+                    chunk.indices.push(Index { surface: *surface, ..Index::from([x, y, z]) });
+                }
+            }
+
+            if let Some((added_vertices, added_indices)) = added.remove(&chunk_pos) {
+                let offset = chunk.vertices.len() as u32;
+                chunk.vertices.extend(added_vertices);
+                chunk.indices.extend(added_indices.into_iter().map(|index| Index { x: index.x + offset, y: index.y + offset, z: index.z + offset, ..index }));
+            }
+
+            let meshlet = (!chunk.indices.is_empty()).then(|| build_meshlet(chunk_pos, chunk.vertices, chunk.indices, settings));
+            (chunk_pos, meshlet)
+        })
+        .collect())
+}
+
+/// Merges the chunks split from different meshes of the same map, so every chunk ends up with a single trimesh
+/// per level of detail, and a feature ID always points at one triangle. A mesh with fewer levels keeps using its coarsest one.
+/// Merged chunks lose their heightfields, since the meshes can overlap.
+pub fn merge_meshlets(meshlets: Vec<Meshlet>) -> Vec<Meshlet> {
+    let mut chunks: HashMap<ChunkPos, Vec<Meshlet>> = HashMap::new();
+    for meshlet in meshlets {
+        chunks.entry(meshlet.chunk_pos).or_default().push(meshlet);
+    }
+
+    chunks.into_iter()
+        .map(|(chunk_pos, mut meshes)| {
+            if meshes.len() == 1 {
+                return meshes.pop().unwrap();
+            }
+            concat_meshlets(chunk_pos, &meshes)
+        })
+        .collect()
+}
+
+/// Every level of detail of `meshes` appended into one meshlet, without a heightfield
+fn concat_meshlets(chunk_pos: ChunkPos, meshes: &[Meshlet]) -> Meshlet {
+    let lod_count = meshes.iter().map(|meshlet| meshlet.lods.len()).max().unwrap_or(0);
+    let mut full_detail = MeshletLod::default();
+    let mut lods = vec![MeshletLod::default(); lod_count];
+
+    for meshlet in meshes {
+        full_detail.append(&meshlet.vertices, &meshlet.indices, &meshlet.surfaces);
+        for (level, lod) in lods.iter_mut().enumerate() {
+            match meshlet.lods.get(level).or(meshlet.lods.last()) {
+                Some(source) => lod.append(&source.vertices, &source.indices, &source.surfaces),
+                None => lod.append(&meshlet.vertices, &meshlet.indices, &meshlet.surfaces),
+            }
+        }
+    }
+
+    Meshlet {
+        chunk_pos,
+        vertices: full_detail.vertices,
+        indices: full_detail.indices,
+        surfaces: full_detail.surfaces,
+        lods,
+        heightfield: None,
+    }
+}
+
+/// Limits for the cells of a [`ChunkTree`]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ChunkTreeSettings {
+    /// Cells with more triangles than this are split into four, unless they are a single grid chunk
+    pub max_triangles: usize,
+    /// The biggest cells are `2^max_level` grid chunks across
+    pub max_level: u32,
+}
+
+/// Quadtree over the chunk grid, so sparse areas are covered by a few big chunks and dense areas by many small ones.
+/// A leaf at level `l` covers `2^l` x `2^l` grid chunks of one layer, and is keyed by the grid chunk at its lowest corner,
+/// so leaves are used wherever a [`ChunkPos`] is. Leaves only ever merge chunks along x and z.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChunkTree {
+    // level of every leaf bigger than a single grid chunk - any other grid chunk is a leaf of its own
+    leaves: HashMap<ChunkPos, u32>,
+    max_level: u32,
+}
+
+impl ChunkTree {
+    /// Splits cells from the top down until every leaf holds at most `max_triangles` of `triangle_counts`,
+    /// the number of triangles in each grid chunk, or is a single grid chunk
+    pub fn new(triangle_counts: &HashMap<ChunkPos, usize>, settings: &ChunkTreeSettings) -> Self {
+        // triangles in every cell of every level, keyed by the cell's position on that level
+        let mut counts = vec![triangle_counts.clone()];
+        for _ in 0..settings.max_level {
+            let mut parents: HashMap<ChunkPos, usize> = HashMap::new();
+            for (cell, count) in counts.last().unwrap() {
+                *parents.entry(ChunkPos { x: cell.x >> 1, y: cell.y, z: cell.z >> 1 }).or_default() += count;
+            }
+            counts.push(parents);
+        }
+
+        let mut leaves = HashMap::new();
+        let mut cells: Vec<(ChunkPos, u32)> = counts[settings.max_level as usize].keys().map(|cell| (*cell, settings.max_level)).collect();
+
+        while let Some((cell, level)) = cells.pop() {
+            if level == 0 {
+                continue;
+            }
+            if counts[level as usize][&cell] <= settings.max_triangles {
+                leaves.insert(ChunkPos { x: cell.x << level, y: cell.y, z: cell.z << level }, level);
+                continue;
+            }
+
+            let children = &counts[level as usize - 1];
+            for (x, z) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let child = ChunkPos { x: cell.x * 2 + x, y: cell.y, z: cell.z * 2 + z };
+                if children.contains_key(&child) {
+                    cells.push((child, level - 1));
+                }
+            }
+        }
+
+        Self {
+            leaves,
+            max_level: settings.max_level,
+        }
+    }
+
+    /// The leaf covering a grid chunk
+    pub fn leaf(&self, chunk_pos: ChunkPos) -> ChunkPos {
+        (1..=self.max_level)
+            .map(|level| (ChunkPos { x: chunk_pos.x >> level << level, y: chunk_pos.y, z: chunk_pos.z >> level << level }, level))
+            .find(|(leaf, level)| self.leaves.get(leaf) == Some(level))
+            .map_or(chunk_pos, |(leaf, _)| leaf)
+    }
+
+    /// Level of a leaf, 0 being a single grid chunk
+    pub fn level(&self, leaf: ChunkPos) -> u32 {
+        self.leaves.get(&leaf).copied().unwrap_or(0)
+    }
+
+    /// The grid chunk at the highest corner of a leaf
+    pub fn last_chunk(&self, leaf: ChunkPos) -> ChunkPos {
+        let size = 1 << self.level(leaf);
+        ChunkPos { x: leaf.x + size - 1, y: leaf.y, z: leaf.z + size - 1 }
+    }
+
+    /// How many rings of grid chunks around `chunk_pos` there are before reaching a leaf, 0 if the leaf covers it
+    pub fn ring(&self, leaf: ChunkPos, chunk_pos: ChunkPos) -> i32 {
+        let last = self.last_chunk(leaf);
+        let distance = |min: i32, max: i32, at: i32| (min - at).max(at - max).max(0);
+
+        distance(leaf.x, last.x, chunk_pos.x)
+            .max((leaf.y - chunk_pos.y).abs())
+            .max(distance(leaf.z, last.z, chunk_pos.z))
+    }
+}
+
+/// Builds a [`ChunkTree`] over meshlets split on the chunk grid, and merges them into one meshlet per leaf.
+/// Run it once every mesh of a map is split and merged with [`merge_meshlets`], since the tree depends on all of them.
+pub fn build_chunk_tree(meshlets: Vec<Meshlet>, tree_settings: &ChunkTreeSettings, settings: &SplitSettings) -> (ChunkTree, Vec<Meshlet>) {
+    let triangle_counts = meshlets.iter().map(|meshlet| (meshlet.chunk_pos, meshlet.indices.len())).collect();
+    let tree = ChunkTree::new(&triangle_counts, tree_settings);
+
+    let mut leaves: HashMap<ChunkPos, Vec<Meshlet>> = HashMap::new();
+    for meshlet in meshlets {
+        leaves.entry(tree.leaf(meshlet.chunk_pos)).or_default().push(meshlet);
+    }
+
+    let meshlets = leaves.into_iter()
+        .map(|(leaf, mut meshes)| {
+            // meshlets are stored in the mesh's space, so a lone chunk only needs its new key
+            if meshes.len() == 1 {
+                let mut meshlet = meshes.pop().unwrap();
+                meshlet.chunk_pos = leaf;
+                return meshlet;
+            }
+
+            // the fit turns down overlapping layers, so a heightfield is only kept where the chunks form one surface
+            let mut meshlet = concat_meshlets(leaf, &meshes);
+            meshlet.heightfield = fit_heightfield(&meshlet, settings);
+            meshlet
+        })
+        .collect();
+
+    (tree, meshlets)
+}
+
+/// Colour of a chunk in debug exports - neighbours almost always differ, and a chunk keeps its colour between exports
+fn debug_color(chunk_pos: ChunkPos) -> Srgba {
+    let hash = (chunk_pos.x as u32).wrapping_mul(73_856_093) ^ (chunk_pos.y as u32).wrapping_mul(19_349_663) ^ (chunk_pos.z as u32).wrapping_mul(83_492_791);
+    // mix as an integer, since f32 drops the fraction of anything above 2^24 - the multiplier is 2^32 over the golden ratio,
+    // which spreads consecutive hashes around the hue circle, and the top 24 bits fit an f32 exactly
+    let hue = (hash.wrapping_mul(0x9E37_79B9) >> 8) as f32 / (1 << 24) as f32 * 360.0;
+    Hsla::hsl(hue, 0.7, 0.55).into()
+}
+
+fn debug_name(chunk_pos: ChunkPos) -> String {
+    format!("chunk_{}_{}_{}", chunk_pos.x, chunk_pos.y, chunk_pos.z)
+}
+
+/// Writes the 12 edges of the box between `min` and `max` as OBJ lines, after its 8 corners
+fn write_debug_box(obj: &mut String, vertex_count: &mut u32, min: Vec3, max: Vec3) {
+    for corner in 0..8 {
+        let corner = Vec3::select(BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0), max, min);
+        writeln!(obj, "v {} {} {}", corner.x, corner.y, corner.z).unwrap();
+    }
+    // edges run between corners that differ in one bit
+    for (a, b) in [(0, 1), (2, 3), (4, 5), (6, 7), (0, 2), (1, 3), (4, 6), (5, 7), (0, 4), (1, 5), (2, 6), (3, 7)] {
+        writeln!(obj, "l {} {}", *vertex_count + a + 1, *vertex_count + b + 1).unwrap();
+    }
+    *vertex_count += 8;
+}
+
+/// The full detail triangles of every grid chunk as its own named, colour-tinted OBJ object, followed by a `chunk_grid`
+/// object with the outline of every chunk's cell and a `chunk_tree` object with the outline of every leaf of `tree`
+/// that merges several of them, so the split can be inspected in Blender and the like.
+/// Expects the meshlets [`try_split_meshlets`] returns, before [`build_chunk_tree`] merges them, so
+/// boundary triangles copied into several chunks show up as overlapping colours, and missing ones as holes.
+/// Returns the OBJ and the MTL file it loads its materials from, which has to be saved as `mtl_file_name` next to it.
+pub fn debug_obj(meshlets: &[Meshlet], grid: &ChunkGrid, tree: &ChunkTree, mtl_file_name: &str) -> (String, String) {
+    let mut meshlets: Vec<&Meshlet> = meshlets.iter().collect();
+    meshlets.sort_by_key(|meshlet| (meshlet.chunk_pos.x, meshlet.chunk_pos.y, meshlet.chunk_pos.z));
+
+    let mut obj = format!("mtllib {mtl_file_name}\n");
+    let mut mtl = String::new();
+    // OBJ indices are 1-based and count every vertex before them in the file
+    let mut vertex_count = 0;
+
+    for meshlet in &meshlets {
+        let name = debug_name(meshlet.chunk_pos);
+        let color = debug_color(meshlet.chunk_pos);
+        writeln!(mtl, "newmtl {name}\nKd {} {} {}\n", color.red, color.green, color.blue).unwrap();
+
+        writeln!(obj, "o {name}\nusemtl {name}").unwrap();
+        for vertex in &meshlet.vertices {
+            writeln!(obj, "v {} {} {}", vertex.x, vertex.y, vertex.z).unwrap();
+        }
+        for [a, b, c] in &meshlet.indices {
+            writeln!(obj, "f {} {} {}", vertex_count + a + 1, vertex_count + b + 1, vertex_count + c + 1).unwrap();
+        }
+        vertex_count += meshlet.vertices.len() as u32;
+    }
+
+    // chunks without a cell height are outlined from their lowest to their highest vertex, and leaves around all of their chunks
+    let mut leaves: Vec<(ChunkPos, f32, f32)> = Vec::new();
+    writeln!(obj, "o chunk_grid").unwrap();
+    for meshlet in &meshlets {
+        let (bottom, top) = meshlet.vertices.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(bottom, top), vertex| (bottom.min(vertex.y), top.max(vertex.y)));
+        let (min, max) = (grid.chunk_min(meshlet.chunk_pos), grid.chunk_max(meshlet.chunk_pos));
+        write_debug_box(&mut obj, &mut vertex_count,
+            Vec3::new(min.x, if min.y.is_finite() { min.y } else { bottom }, min.z),
+            Vec3::new(max.x, if max.y.is_finite() { max.y } else { top }, max.z));
+
+        let leaf = tree.leaf(meshlet.chunk_pos);
+        match leaves.iter_mut().find(|(other, _, _)| *other == leaf) {
+            Some((_, leaf_bottom, leaf_top)) => (*leaf_bottom, *leaf_top) = (leaf_bottom.min(bottom), leaf_top.max(top)),
+            None => leaves.push((leaf, bottom, top)),
+        }
+    }
+
+    // single chunk leaves are already outlined by the grid
+    writeln!(obj, "o chunk_tree").unwrap();
+    for (leaf, bottom, top) in leaves.into_iter().filter(|(leaf, _, _)| tree.level(*leaf) > 0) {
+        let (min, max) = (grid.chunk_min(leaf), grid.chunk_max(tree.last_chunk(leaf)));
+        write_debug_box(&mut obj, &mut vertex_count,
+            Vec3::new(min.x, if min.y.is_finite() { min.y } else { bottom }, min.z),
+            Vec3::new(max.x, if max.y.is_finite() { max.y } else { top }, max.z));
+    }
+
+    (obj, mtl)
+}
+
+/// Writes [`debug_obj`] to `path`, with its materials next to it
+pub fn export_debug_obj(meshlets: &[Meshlet], grid: &ChunkGrid, tree: &ChunkTree, path: &Path) -> io::Result<()> {
+    let mtl_path = path.with_extension("mtl");
+    let mtl_file_name = mtl_path.file_name().and_then(|name| name.to_str()).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "export path has no file name"))?;
+
+    let (obj, mtl) = debug_obj(meshlets, grid, tree, mtl_file_name);
+    fs::write(path, obj)?;
+    fs::write(&mtl_path, mtl)
+}
+
+/// Colliders for every level of detail of every chunk, full detail first
+pub fn to_subcolliders(meshlets: &[Meshlet]) -> Vec<(ChunkPos, ChunkColliders)> {
+    meshlets.iter()
+        .map(|meshlet| (meshlet.chunk_pos, meshlet.to_lod_colliders()))
+        .collect()
+}
+
+/// Same as [`to_subcolliders`], but builds the colliders of several buckets of chunks at once
+pub fn to_subcolliders_parallel(meshlets: &[Meshlet], task_pool: &TaskPool) -> Vec<(ChunkPos, ChunkColliders)> {
+    // one bucket per thread keeps the scheduling overhead low while still using every core
+    let bucket_size = meshlets.len().div_ceil(task_pool.thread_num().max(1)).max(1);
+
+    task_pool.scope(|scope| {
+        for bucket in meshlets.chunks(bucket_size) {
+            scope.spawn(async move { to_subcolliders(bucket) });
+        }
+    })
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+pub fn try_split_subcolliders(mesh: &Mesh, settings: &SplitSettings) -> Result<Vec<(ChunkPos, ChunkColliders)>, SplitError> {
+    Ok(to_subcolliders(&try_split_meshlets(mesh, settings)?))
+}
+
+/// Same as [`try_split_subcolliders`], but also returns the [`SplitReport`] of the split
+pub fn try_split_subcolliders_with_report(mesh: &Mesh, settings: &SplitSettings) -> Result<(Vec<(ChunkPos, ChunkColliders)>, SplitReport), SplitError> {
+    let (meshlets, report) = try_split_meshlets_with_report(mesh, settings)?;
+    Ok((to_subcolliders(&meshlets), report))
+}
+
+/// Same as [`try_split_subcolliders`], but logs the error and returns no colliders if the mesh can't be split
+pub fn split_subcolliders(mesh: &Mesh, settings: &SplitSettings) -> Vec<(ChunkPos, ChunkColliders)> {
+    try_split_subcolliders(mesh, settings).unwrap_or_else(|err| {
+        warn!("Failed to split mesh into subcolliders: {}", err);
+        Vec::new()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(x: f32, y: f32, z: f32) -> Vertex {
+        Vertex::from([x, y, z])
+    }
+
+    fn index(x: u32, y: u32, z: u32) -> Index {
+        Index::from([x, y, z])
+    }
+
+    /// Every chunk's triangles as sorted lists of corner positions, so outputs can be compared regardless of vertex order
+    fn triangle_positions(chunks: &SplitChunks) -> HashMap<ChunkPos, Vec<[[u32; 3]; 3]>> {
+        chunks.iter()
+            .filter(|(_, (_, indices))| !indices.is_empty())
+            .map(|(chunk_pos, (vertices, indices))| {
+                let mut triangles: Vec<[[u32; 3]; 3]> = indices.iter()
+                    .map(|idx| {
+                        let mut corners = [idx.x, idx.y, idx.z].map(|i| vertices[i as usize].bits());
+                        corners.sort_unstable();
+                        corners
+                    })
+                    .collect();
+                triangles.sort_unstable();
+                (*chunk_pos, triangles)
+            })
+            .collect()
+    }
+
+    /// Two triangles of a quad, with the shared edge duplicated as if split along a UV seam
+    fn seam_quad() -> (Vec<Vertex>, Vec<Index>) {
+        (
+            vec![
+                vertex(0.0, 0.0, 0.0), vertex(1.0, 0.0, 0.0), vertex(1.0, 0.0, 1.0),
+                vertex(0.0, 0.0, 0.0), vertex(1.0, 0.0, 1.0), vertex(0.0, 0.0, 1.0),
+            ],
+            vec![index(0, 1, 2), index(3, 4, 5)],
+        )
+    }
+
+    #[test]
+    fn clipped_pieces_tile_the_triangle_inside_their_cells() {
+        let settings = SplitSettings::new(1.0).with_boundary_mode(BoundaryMode::Clip);
+        let grid = settings.grid();
+
+        // tilted triangles straddling two and four cells
+        for (corners, cell_count) in [
+            ([vertex(0.2, 0.0, 0.2), vertex(0.4, 0.5, 0.8), vertex(1.7, 0.3, 0.5)], 2),
+            ([vertex(0.2, 0.0, 0.2), vertex(0.3, 0.5, 1.8), vertex(1.7, 0.3, 0.6)], 4),
+        ] {
+            let source = corners.map(Vertex::to_vec3);
+            let source_normal = (source[1] - source[0]).cross(source[2] - source[0]);
+
+            let chunks = split_mesh(corners.to_vec(), vec![index(0, 1, 2)], &settings).unwrap();
+            assert_eq!(chunks.len(), cell_count);
+
+            let mut area = 0.0;
+            for (chunk_pos, (vertices, indices)) in &chunks {
+                let (min, max) = (grid.chunk_min(*chunk_pos), grid.chunk_max(*chunk_pos));
+                for vertex in vertices {
+                    let vertex = vertex.to_vec3();
+                    assert!(vertex.x >= min.x - 1e-6 && vertex.x <= max.x + 1e-6 && vertex.z >= min.z - 1e-6 && vertex.z <= max.z + 1e-6,
+                        "{vertex} leaks out of chunk {chunk_pos:?}");
+                }
+
+                for index in indices {
+                    let [a, b, c] = [index.x, index.y, index.z].map(|i| vertices[i as usize].to_vec3());
+                    let normal = (b - a).cross(c - a);
+                    assert!(normal.normalize().dot(source_normal.normalize()) > 0.9999, "piece in {chunk_pos:?} flipped or tilted");
+                    area += normal.length() / 2.0;
+                }
+            }
+
+            assert!((area - source_normal.length() / 2.0).abs() < 1e-5, "pieces cover {area}, not the triangle");
+        }
+    }
+
+    #[test]
+    fn cleanup_welds_seam_vertices() {
+        let (vertices, indices) = seam_quad();
+        let (cleaned_vertices, cleaned_indices, report) = clean(&vertices, &indices, 0.001);
+
+        assert_eq!(cleaned_vertices.len(), 4);
+        assert_eq!(cleaned_indices.len(), 2);
+        assert_eq!(report, CleanupReport { welded_vertices: 2, ..CleanupReport::default() });
+    }
+
+    #[test]
+    fn cleanup_welds_within_tolerance_only() {
+        let vertices = vec![vertex(0.0, 0.0, 0.0), vertex(0.0005, 0.0, 0.0), vertex(0.01, 0.0, 0.0)];
+        let (welded, remap) = weld(&vertices, 0.001);
+
+        assert_eq!(welded.len(), 2);
+        assert_eq!(remap, vec![0, 0, 1]);
+    }
+
+    #[test]
+    fn cleanup_drops_degenerate_and_duplicate_triangles() {
+        let (mut vertices, mut indices) = seam_quad();
+        // a sliver collapsing onto a welded vertex, a collinear triangle, a rotated copy of the first triangle
+        // and a flipped one, which is the back of a two-sided wall and has to stay
+        vertices.extend([vertex(0.0, 0.0, 0.0001), vertex(2.0, 0.0, 0.0), vertex(3.0, 0.0, 0.0)]);
+        indices.extend([index(0, 6, 1), index(0, 7, 8), index(1, 2, 0), index(2, 1, 0)]);
+
+        let (cleaned_vertices, cleaned_indices, report) = clean(&vertices, &indices, 0.001);
+
+        assert_eq!(cleaned_indices.len(), 3);
+        assert_eq!(cleaned_indices[2], index(2, 1, 0));
+        assert_eq!(cleaned_vertices.len(), 4);
+        assert_eq!(report, CleanupReport {
+            welded_vertices: 3,
+            unused_vertices: 2,
+            degenerate_triangles: 2,
+            duplicate_triangles: 1,
+        });
+    }
+
+    #[test]
+    fn cleaned_mesh_splits_like_clean_source() {
+        let settings = SplitSettings::new(10.0);
+        let (vertices, indices) = seam_quad();
+        let (cleaned_vertices, cleaned_indices, _) = clean(&vertices, &indices, 0.001);
+
+        let clean_source = (
+            vec![vertex(0.0, 0.0, 0.0), vertex(1.0, 0.0, 0.0), vertex(1.0, 0.0, 1.0), vertex(0.0, 0.0, 1.0)],
+            vec![index(0, 1, 2), index(0, 2, 3)],
+        );
+
+        let cleaned_split = split_mesh(cleaned_vertices, cleaned_indices, &settings).unwrap();
+        let source_split = split_mesh(clean_source.0, clean_source.1, &settings).unwrap();
+
+        assert_eq!(triangle_positions(&cleaned_split), triangle_positions(&source_split));
+        // welding doesn't change the surface, only how many vertices describe it
+        assert_eq!(triangle_positions(&cleaned_split), triangle_positions(&split_mesh(vertices, indices, &settings).unwrap()));
+    }
+
+    #[test]
+    fn clipped_pieces_keep_their_surface() {
+        let settings = SplitSettings::new(1.0).with_boundary_mode(BoundaryMode::Clip);
+        // two triangles crossing several chunks, the first one grass and the second one rock
+        let vertices = vec![
+            vertex(0.2, 0.0, 0.2), vertex(3.7, 0.0, 0.4), vertex(0.5, 0.0, 2.9),
+            vertex(0.2, 0.0, 5.2), vertex(3.7, 0.0, 5.4), vertex(0.5, 0.0, 7.9),
+        ];
+        let indices = vec![
+            Index { surface: SurfaceId(1), ..index(0, 2, 1) },
+            Index { surface: SurfaceId(2), ..index(3, 5, 4) },
+        ];
+
+        let chunks = split_mesh(vertices, indices, &settings).unwrap();
+        assert!(chunks.len() > 2);
+
+        for (chunk_pos, (_, indices)) in &chunks {
+            let expected = if chunk_pos.z < 4 { SurfaceId(1) } else { SurfaceId(2) };
+            assert!(indices.iter().all(|index| index.surface == expected), "chunk {chunk_pos:?} mixed up surfaces");
+        }
+    }
+
+    #[test]
+    fn split_report_accounts_for_every_triangle() {
+        use bevy::render::render_asset::RenderAssetUsages;
+
+        // a triangle straddling two chunks, one inside the first chunk, and a vertex no triangle uses
+        let positions = vec![
+            [0.5, 0.0, 0.5], [1.5, 0.0, 0.5], [0.5, 0.0, 0.9],
+            [0.1, 0.0, 0.1], [0.1, 0.0, 0.2], [0.2, 0.0, 0.1],
+            [5.5, 0.0, 5.5],
+        ];
+        let mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default())
+            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+            .with_inserted_indices(Indices::U32(vec![0, 2, 1, 3, 4, 5]));
+
+        let (meshlets, report) = try_split_meshlets_with_report(&mesh, &SplitSettings::new(1.0)).unwrap();
+        assert_eq!(meshlets.len(), 2);
+        assert!(report.is_valid());
+        assert_eq!((report.source_triangles, report.emitted_triangles), (2, 3));
+        assert_eq!((report.duplicated_triangles, report.empty_chunks_dropped), (1, 1));
+        assert_eq!(report.histogram(), vec![1, 1]);
+        assert_eq!(report.chunks[1], ChunkReport {
+            chunk_pos: ChunkPos { x: 1, y: 0, z: 0 },
+            triangles: 1,
+            min: Vec3::new(0.5, 0.0, 0.5),
+            max: Vec3::new(1.5, 0.0, 0.9),
+        });
+
+        // triangles keep their position in the mesh through cleanup, so reports point at the mesh's own triangles
+        let positions = vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0, 1.0]];
+        let mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default())
+            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+            .with_inserted_indices(Indices::U32(vec![0, 0, 1, 0, 2, 1, 2, 1, 0, 1, 2, 3]));
+        let (_, indices, _) = prepare_mesh(&mesh, &SplitSettings::new(1.0).with_cleanup(0.001)).unwrap();
+        assert_eq!(indices.iter().map(|index| index.source).collect::<Vec<_>>(), vec![1, 3]);
+
+        // a straddling triangle with no area leaves no clipped pieces behind
+        let sliver = vec![vertex(0.5, 0.0, 0.5), vertex(1.5, 0.0, 0.5), vertex(0.5, 0.0, 0.5 + 1e-9)];
+        let chunks = split_mesh(sliver, vec![index(0, 2, 1)], &SplitSettings::new(1.0).with_boundary_mode(BoundaryMode::Clip)).unwrap();
+        assert_eq!(SplitReport::new(&chunks, &[0], None).missing_triangles, vec![0]);
+    }
+
+    #[test]
+    fn incomplete_triangles_are_rejected() {
+        use bevy::render::render_asset::RenderAssetUsages;
+
+        let positions = vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0, 1.0]];
+        let indexed = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default())
+            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions.clone())
+            .with_inserted_indices(Indices::U16(vec![0, 2, 1, 1, 2]));
+        let non_indexed = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default())
+            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+
+        assert_eq!(to_vertices(&indexed).err(), Some(SplitError::IncompleteTriangle(5)));
+        assert_eq!(to_vertices(&non_indexed).err(), Some(SplitError::IncompleteTriangle(4)));
+    }
+
+    #[test]
+    fn strips_and_non_indexed_lists_are_read() {
+        use bevy::render::render_asset::RenderAssetUsages;
+
+        // two quads in a row, joined into one strip by repeating the last index of the first and the first of the second
+        let positions = vec![
+            [0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [1.0, 0.0, 1.0],
+            [2.0, 0.0, 0.0], [2.0, 0.0, 1.0], [3.0, 0.0, 0.0], [3.0, 0.0, 1.0],
+        ];
+        let strip = |indices: Vec<u16>| Mesh::new(PrimitiveTopology::TriangleStrip, RenderAssetUsages::default())
+            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions.clone())
+            .with_inserted_indices(Indices::U16(indices));
+        let up = |vertices: &[Vertex], index: &Index| {
+            let [a, b, c] = [index.x, index.y, index.z].map(|i| vertices[i as usize].to_vec3());
+            (b - a).cross(c - a).normalize() == Vec3::Y
+        };
+
+        // every other triangle of a strip is flipped back, so both triangles of a quad face the same way
+        let (vertices, indices) = to_vertices(&strip(vec![0, 1, 2, 3])).unwrap();
+        assert_eq!(indices.len(), 2);
+        assert!(indices.iter().all(|index| up(&vertices, index)));
+
+        // the joining triangles are dropped even with cleanup off, and the second quad keeps facing up
+        let (vertices, indices) = to_vertices(&strip(vec![0, 1, 2, 3, 3, 4, 4, 5, 6, 7])).unwrap();
+        assert_eq!(indices.iter().map(|index| index.source).collect::<Vec<_>>(), vec![0, 1, 6, 7]);
+        assert!(indices.iter().all(|index| up(&vertices, index)));
+        let chunks = split_bevy_mesh(&strip(vec![0, 1, 2, 3, 3, 4, 4, 5, 6, 7]), &SplitSettings::new(10.0)).unwrap();
+        assert_eq!(chunks.values().map(|(_, indices)| indices.len()).sum::<usize>(), 4);
+
+        // triangle lists without indices use every vertex once, in order
+        let non_indexed = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default())
+            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions[..6].to_vec());
+        let (_, indices) = to_vertices(&non_indexed).unwrap();
+        assert_eq!(indices, vec![
+            Index { source: 0, ..index(0, 1, 2) },
+            Index { source: 1, ..index(3, 4, 5) },
+        ]);
+
+        for topology in [PrimitiveTopology::LineList, PrimitiveTopology::LineStrip, PrimitiveTopology::PointList] {
+            let mesh = Mesh::new(topology, RenderAssetUsages::default())
+                .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions.clone());
+            assert_eq!(to_vertices(&mesh).err(), Some(SplitError::UnsupportedTopology(topology)));
+        }
+    }
+
+    /// Splits the triangles and checks what every split has to guarantee: each triangle that isn't a sliver ends up in some chunk,
+    /// local indices point at chunk vertices, and chunk triangles are either their source triangle or a piece of it inside the chunk
+    fn assert_split_invariants(vertices: Vec<Vertex>, indices: Vec<Index>, settings: &SplitSettings) {
+        let grid = settings.grid();
+        let indices: Vec<Index> = indices.into_iter().enumerate().map(|(source, index)| Index { source: source as u32, ..index }).collect();
+        let sources: Vec<[Vertex; 3]> = indices.iter().map(|idx| [idx.x, idx.y, idx.z].map(|i| vertices[i as usize])).collect();
+        let chunks = split_mesh(vertices, indices, settings).unwrap();
+
+        let source_positions: Vec<u32> = (0..sources.len() as u32).collect();
+        let report = SplitReport::new(&chunks, &source_positions, None);
+        for missing in report.missing_triangles {
+            let [a, b, c] = sources[missing].map(Vertex::to_vec3);
+            // slivers thinner than f32 can resolve next to their longest edge may only leave pieces without an area
+            let longest_edge = a.distance_squared(b).max(b.distance_squared(c)).max(c.distance_squared(a));
+            assert!((b - a).cross(c - a).length() <= longest_edge * f32::EPSILON, "triangle {missing} {:?} is in no chunk", sources[missing]);
+        }
+
+        for (chunk_pos, (chunk_vertices, chunk_indices)) in &chunks {
+            for index in chunk_indices {
+                let corners = [index.x, index.y, index.z].map(|local_idx| {
+                    assert!((local_idx as usize) < chunk_vertices.len(), "chunk {chunk_pos:?} indexes vertex {local_idx} of {}", chunk_vertices.len());
+                    chunk_vertices[local_idx as usize].to_vec3()
+                });
+                let source = sources[index.source as usize].map(Vertex::to_vec3);
+
+                if settings.boundary_mode == BoundaryMode::Duplicate {
+                    assert_eq!(corners, source, "chunk {chunk_pos:?} changed triangle {}", index.source);
+                    assert!(source.iter().any(|corner| grid.local_to_chunk(*corner) == *chunk_pos),
+                        "triangle {} went into chunk {chunk_pos:?} none of its corners lie in", index.source);
+                    continue;
+                }
+
+                // clipped corners lie on the source triangle, within the chunk's cell
+                let epsilon = source.iter().fold(1.0, |scale, corner| corner.abs().max_element().max(scale)) * 1e-4;
+                let normal = (source[1] - source[0]).cross(source[2] - source[0]).normalize_or_zero();
+                let source_min = source[0].min(source[1]).min(source[2]) - epsilon;
+                let source_max = source[0].max(source[1]).max(source[2]) + epsilon;
+                let cell_min = grid.chunk_min(*chunk_pos) - epsilon;
+                let cell_max = grid.chunk_max(*chunk_pos) + epsilon;
+
+                for corner in corners {
+                    assert!(corner.cmpge(source_min).all() && corner.cmple(source_max).all() && normal.dot(corner - source[0]).abs() <= epsilon,
+                        "corner {corner} in chunk {chunk_pos:?} isn't on triangle {} {source:?}", index.source);
+                    assert!(corner.cmpge(cell_min).all() && corner.cmple(cell_max).all(),
+                        "corner {corner} of triangle {} leaks out of chunk {chunk_pos:?}", index.source);
+                }
+            }
+        }
+    }
+
+    fn random_vec3(rng: &mut fastrand::Rng, range: f32) -> Vec3 {
+        Vec3::new(rng.f32() * 2.0 - 1.0, rng.f32() * 2.0 - 1.0, rng.f32() * 2.0 - 1.0) * range
+    }
+
+    /// A soup of triangles sharing some of their corners, with sizes up to `max_size` scattered over `-range..range`
+    fn random_mesh(rng: &mut fastrand::Rng, triangles: usize, range: f32, max_size: f32) -> (Vec<Vertex>, Vec<Index>) {
+        let mut vertices: Vec<Vertex> = Vec::new();
+        let mut indices = Vec::new();
+
+        for _ in 0..triangles {
+            // about half the triangles grow out of a corner of an earlier one
+            let first = if !vertices.is_empty() && rng.bool() {
+                rng.u32(..vertices.len() as u32)
+            } else {
+                vertices.push(Vertex::from(random_vec3(rng, range)));
+                vertices.len() as u32 - 1
+            };
+
+            let first_vertex = vertices[first as usize].to_vec3();
+            vertices.push(Vertex::from(first_vertex + random_vec3(rng, max_size)));
+            vertices.push(Vertex::from(first_vertex + random_vec3(rng, max_size)));
+            indices.push(index(first, vertices.len() as u32 - 2, vertices.len() as u32 - 1));
+        }
+
+        (vertices, indices)
+    }
+
+    fn all_settings(chunk_size: f32) -> [SplitSettings; 4] {
+        [
+            SplitSettings::new(chunk_size),
+            SplitSettings::new(chunk_size).with_chunk_height(chunk_size / 2.0),
+            SplitSettings::new(chunk_size).with_boundary_mode(BoundaryMode::Clip),
+            SplitSettings::new(chunk_size).with_chunk_height(chunk_size / 2.0).with_boundary_mode(BoundaryMode::Clip),
+        ]
+    }
+
+    #[test]
+    fn split_invariants_hold_for_random_meshes() {
+        let mut rng = fastrand::Rng::with_seed(25);
+
+        for _ in 0..20 {
+            let (vertices, indices) = random_mesh(&mut rng, 200, 20.0, 6.0);
+            for settings in all_settings(rng.f32() * 4.0 + 0.5) {
+                assert_split_invariants(vertices.clone(), indices.clone(), &settings);
+            }
+        }
+    }
+
+    #[test]
+    fn split_invariants_hold_for_adversarial_meshes() {
+        let meshes = [
+            // a single triangle over hundreds of chunks
+            (vec![vertex(-150.3, 0.0, -150.7), vertex(150.1, 5.0, -149.9), vertex(0.2, -3.0, 150.4)], vec![index(0, 2, 1)]),
+            // negative coordinates and corners right next to the chunk border at zero
+            (
+                vec![vertex(-0.0, 0.0, -0.0), vertex(-1e-7, 0.0, 0.5), vertex(-3.5, -2.0, -0.0), vertex(1e-7, -1e-7, -4.25)],
+                vec![index(0, 1, 2), index(0, 3, 1), index(2, 3, 0)],
+            ),
+            // a triangle around the origin so small that the square of its area underflows
+            (vec![vertex(-8.869637e-25, -1.5472108e-33, 4.000246e-9), vertex(6.992923e-15, 1.3219704e-32, -1.0546574e-11), vertex(6.8419945e-38, 0.0, 7.19e-43)], vec![index(0, 1, 2)]),
+            // corners and whole edges exactly on grid lines, and a wall lying in a grid plane
+            (
+                vec![vertex(0.0, 0.0, 0.0), vertex(2.0, 0.0, 0.0), vertex(2.0, 0.0, 2.0), vertex(1.0, 1.0, 3.0),
+                    vertex(2.0, 4.0, 0.0), vertex(2.0, 4.0, 2.0)],
+                vec![index(0, 2, 1), index(0, 3, 2), index(1, 2, 4), index(4, 2, 5)],
+            ),
+            // far from the origin, where f32 steps are coarse
+            (vec![vertex(1e6, 3.0, -1e6), vertex(1e6 + 2.5, 3.0, -1e6), vertex(1e6, 5.0, -1e6 + 2.5)], vec![index(0, 2, 1)]),
+            // degenerate triangles across borders, which may vanish when clipped
+            (vec![vertex(0.5, 0.0, 0.5), vertex(1.5, 0.0, 0.5), vertex(2.5, 0.0, 0.5)], vec![index(0, 1, 2), index(0, 0, 1)]),
+        ];
+
+        for (vertices, indices) in meshes {
+            for settings in all_settings(1.0) {
+                assert_split_invariants(vertices.clone(), indices.clone(), &settings);
+            }
+        }
+
+        // vertices no chunk position can address are refused instead of split at the wrong planes,
+        // unless no triangle uses them
+        for far_away in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY, 1e10] {
+            let vertices = vec![vertex(0.25, 0.0, 0.25), vertex(0.75, 0.0, 0.25), vertex(far_away, 0.0, 0.75), vertex(0.25, 0.0, 0.75)];
+            assert_eq!(split_mesh(vertices.clone(), vec![index(0, 2, 1)], &SplitSettings::new(1.0)).err(), Some(SplitError::VertexOutOfGrid(2)));
+
+            let chunks = split_mesh(vertices, vec![index(0, 3, 1)], &SplitSettings::new(1.0)).unwrap();
+            assert!(chunks.values().flat_map(|(vertices, _)| vertices).all(|vertex| vertex.to_vec3().is_finite() && vertex.x.abs() < 2.0));
+            assert_eq!(chunks.values().map(|(_, indices)| indices.len()).sum::<usize>(), 1);
+        }
+    }
+
+    #[test]
+    fn resplit_only_rebuilds_edited_chunks() {
+        use bevy::render::render_asset::RenderAssetUsages;
+
+        // a flat 8x8 grid of quads over 4x4 chunks
+        let settings = SplitSettings::new(2.0).with_boundary_mode(BoundaryMode::Clip);
+        let positions: Vec<[f32; 3]> = (0..81).map(|i| [(i % 9) as f32, 0.0, (i / 9) as f32]).collect();
+        let indices: Vec<u32> = (0..8).cartesian_product(0..8)
+            .flat_map(|(x, z)| {
+                let corner = z * 9 + x;
+                [corner, corner + 9, corner + 1, corner + 1, corner + 9, corner + 10]
+            })
+            .collect();
+        let mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default())
+            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+            .with_inserted_indices(Indices::U32(indices));
+        let meshlets: HashMap<ChunkPos, Meshlet> = try_split_meshlets(&mesh, &settings).unwrap().into_iter()
+            .map(|meshlet| (meshlet.chunk_pos, meshlet))
+            .collect();
+
+        // raise the first chunk, with the new quad reaching halfway into its neighbour
+        let edit = RegionEdit {
+            min: Vec3::new(0.1, -1.0, 0.1),
+            max: Vec3::new(1.9, 1.0, 1.9),
+            vertices: vec![Vec3::new(0.0, 0.5, 0.0), Vec3::new(3.0, 0.5, 0.0), Vec3::new(3.0, 0.5, 2.0), Vec3::new(0.0, 0.5, 2.0)],
+            indices: vec![[0, 3, 1], [1, 3, 2]],
+            surfaces: vec![SurfaceId(7); 2],
+        };
+        let rebuilt: HashMap<ChunkPos, Option<Meshlet>> = try_resplit_region(&meshlets, &ChunkTree::default(), &edit, &settings).unwrap().into_iter().collect();
+
+        let first = ChunkPos { x: 0, y: 0, z: 0 };
+        let neighbour = ChunkPos { x: 1, y: 0, z: 0 };
+        assert_eq!(rebuilt.keys().copied().collect::<HashSet<_>>(), HashSet::from([first, neighbour]));
+
+        let first = rebuilt[&first].as_ref().unwrap();
+        assert!(first.vertices.iter().all(|vertex| vertex.y == 0.5));
+        assert!(first.surfaces.iter().all(|surface| *surface == SurfaceId(7)));
+
+        let neighbour = rebuilt[&neighbour].as_ref().unwrap();
+        let old_triangles = neighbour.surfaces.iter().filter(|surface| **surface == SurfaceId::default()).count();
+        assert_eq!(old_triangles, meshlets[&ChunkPos { x: 1, y: 0, z: 0 }].indices.len());
+        assert!(neighbour.surfaces.len() > old_triangles);
+    }
+
+    #[test]
+    fn render_split_keeps_attributes() {
+        use bevy::render::render_asset::RenderAssetUsages;
+
+        // a 4x4 grid of quads over 2x2 chunks, with UVs and normals that follow the position
+        let positions: Vec<[f32; 3]> = (0..25).map(|i| [(i % 5) as f32, 0.0, (i / 5) as f32]).collect();
+        let uvs: Vec<[f32; 2]> = positions.iter().map(|[x, _, z]| [x / 4.0, z / 4.0]).collect();
+        let normals: Vec<[f32; 3]> = positions.iter().map(|[x, _, _]| [0.0, 1.0, *x]).collect();
+        let indices: Vec<u32> = (0..4).cartesian_product(0..4)
+            .flat_map(|(x, z)| {
+                let corner = z * 5 + x;
+                [corner, corner + 5, corner + 1, corner + 1, corner + 5, corner + 6]
+            })
+            .collect();
+        let mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default())
+            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+            .with_inserted_indices(Indices::U32(indices.clone()));
+
+        let chunks = try_split_render_mesh(&mesh, &ChunkGrid::new(2.0)).unwrap();
+        assert_eq!(chunks.len(), 4);
+
+        let mut triangle_count = 0;
+        for (chunk_pos, chunk_mesh) in &chunks {
+            let Some(VertexAttributeValues::Float32x3(positions)) = chunk_mesh.attribute(Mesh::ATTRIBUTE_POSITION) else { panic!() };
+            let Some(VertexAttributeValues::Float32x2(uvs)) = chunk_mesh.attribute(Mesh::ATTRIBUTE_UV_0) else { panic!() };
+            let Some(VertexAttributeValues::Float32x3(normals)) = chunk_mesh.attribute(Mesh::ATTRIBUTE_NORMAL) else { panic!() };
+
+            for (i, [x, _, z]) in positions.iter().enumerate() {
+                assert_eq!(uvs[i], [x / 4.0, z / 4.0]);
+                assert_eq!(normals[i], [0.0, 1.0, *x]);
+            }
+
+            let Some(Indices::U32(chunk_indices)) = chunk_mesh.indices() else { panic!() };
+            for triangle in chunk_indices.chunks_exact(3) {
+                let centre = triangle.iter().map(|i| Vec3::from(positions[*i as usize])).sum::<Vec3>() / 3.0;
+                assert_eq!(ChunkGrid::new(2.0).local_to_chunk(centre), *chunk_pos);
+            }
+            triangle_count += chunk_indices.len() / 3;
+        }
+        assert_eq!(triangle_count, indices.len() / 3);
+    }
+
+    #[test]
+    fn chunk_tree_adapts_to_density() {
+        // a dense 2x2 block of chunks at the origin in an otherwise sparse 8x8 area, including negative chunks
+        let counts: HashMap<ChunkPos, usize> = (-4..4).cartesian_product(-4..4)
+            .map(|(x, z)| (ChunkPos { x, y: 0, z }, if (0..2).contains(&x) && (0..2).contains(&z) { 50 } else { 1 }))
+            .collect();
+        let tree = ChunkTree::new(&counts, &ChunkTreeSettings { max_triangles: 40, max_level: 3 });
+
+        // the dense chunks stay on their own, the sparse quadrants merge into the biggest cells allowed,
+        // and the rest of the dense quadrant as far as the dense chunks let it
+        let dense = ChunkPos { x: 1, y: 0, z: 1 };
+        assert_eq!(tree.leaf(dense), dense);
+        assert_eq!(tree.level(dense), 0);
+        let sparse = ChunkPos { x: -3, y: 0, z: 2 };
+        assert_eq!(tree.leaf(sparse), ChunkPos { x: -8, y: 0, z: 0 });
+        assert_eq!(tree.level(tree.leaf(sparse)), 3);
+        assert_eq!(tree.leaf(ChunkPos { x: 3, y: 0, z: 0 }), ChunkPos { x: 2, y: 0, z: 0 });
+        assert_eq!(tree.level(ChunkPos { x: 2, y: 0, z: 0 }), 1);
+
+        // every chunk is covered by exactly one leaf
+        let mut covered: HashMap<ChunkPos, usize> = HashMap::new();
+        for leaf in counts.keys().map(|chunk_pos| tree.leaf(*chunk_pos)).unique() {
+            let last = tree.last_chunk(leaf);
+            for chunk_pos in (leaf.x..=last.x).cartesian_product(leaf.z..=last.z).map(|(x, z)| ChunkPos { x, y: 0, z }) {
+                *covered.entry(chunk_pos).or_default() += 1;
+            }
+            assert!(tree.level(leaf) == 0 || (leaf.x..=last.x).cartesian_product(leaf.z..=last.z)
+                .map(|(x, z)| counts.get(&ChunkPos { x, y: 0, z }).copied().unwrap_or(0))
+                .sum::<usize>() <= 40);
+        }
+        assert!(counts.keys().all(|chunk_pos| covered[chunk_pos] == 1));
+
+        assert_eq!(tree.ring(ChunkPos { x: -8, y: 0, z: 0 }, ChunkPos { x: 1, y: 0, z: 1 }), 2);
+        assert_eq!(tree.ring(ChunkPos { x: -8, y: 0, z: 0 }, ChunkPos { x: -3, y: 0, z: 3 }), 0);
+    }
+
+    #[test]
+    fn debug_obj_has_one_object_per_chunk() {
+        let (vertices, indices) = seam_quad();
+        // stretched over two chunks, so one triangle straddles the border
+        let vertices = vertices.into_iter().map(|corner| vertex(corner.x * 3.0, corner.y, corner.z)).collect();
+        let settings = SplitSettings::new(2.0);
+        let meshlets: Vec<Meshlet> = split_mesh(vertices, indices, &settings).unwrap().into_iter()
+            .filter(|(_, (_, indices))| !indices.is_empty())
+            .map(|(chunk_pos, (vertices, indices))| build_meshlet(chunk_pos, vertices, indices, &settings))
+            .collect();
+
+        let (obj, mtl) = debug_obj(&meshlets, &settings.grid(), &ChunkTree::default(), "split.mtl");
+        let lines = |prefix: &str| obj.lines().filter(|line| line.starts_with(prefix)).count();
+
+        assert!(obj.starts_with("mtllib split.mtl\n"));
+        assert_eq!(lines("o chunk_"), meshlets.len() + 2);
+        assert_eq!(lines("usemtl "), meshlets.len());
+        assert_eq!(mtl.lines().filter(|line| line.starts_with("newmtl ")).count(), meshlets.len());
+        assert_eq!(lines("f "), meshlets.iter().map(|meshlet| meshlet.indices.len()).sum::<usize>());
+        assert_eq!(lines("l "), meshlets.len() * 12);
+
+        // neighbouring chunks get different colours
+        let colors: Vec<&str> = mtl.lines().filter(|line| line.starts_with("Kd ")).collect();
+        assert_eq!(colors.len(), meshlets.len());
+        assert!(colors.iter().all_unique());
+        assert_ne!(debug_color(ChunkPos { x: 5, y: 0, z: -3 }), debug_color(ChunkPos { x: 5, y: 0, z: -2 }));
+
+        // every face and line points at a vertex written before it
+        let vertex_count = lines("v ");
+        assert!(obj.lines()
+            .filter(|line| line.starts_with("f ") || line.starts_with("l "))
+            .flat_map(|line| line.split_whitespace().skip(1))
+            .all(|index| (1..=vertex_count).contains(&index.parse().unwrap())));
+
+        // a leaf merging both chunks is outlined on its own, while the chunks stay separate objects
+        let counts = meshlets.iter().map(|meshlet| (meshlet.chunk_pos, meshlet.indices.len())).collect();
+        let tree = ChunkTree::new(&counts, &ChunkTreeSettings { max_triangles: 100, max_level: 1 });
+        let (obj, _) = debug_obj(&meshlets, &settings.grid(), &tree, "split.mtl");
+        assert_eq!(obj.lines().filter(|line| line.starts_with("usemtl ")).count(), meshlets.len());
+        assert_eq!(obj.lines().skip_while(|line| *line != "o chunk_tree").filter(|line| line.starts_with("l ")).count(), 12);
+    }
 }
@@ -0,0 +1,245 @@
+use avian3d::collision::collider::Collider;
+use bevy::prelude::{Quat, Vec2, Vec3};
+
+/// Chunks needing a finer grid than this stay trimeshes
+const MAX_RESOLUTION: usize = 129;
+
+/// A chunk's surface sampled on a regular XZ grid
+#[derive(Clone, Debug, PartialEq)]
+pub struct Heightfield {
+    /// Heights at `resolution` x `resolution` evenly spaced points, indexed `[x][z]`.
+    /// Square, because avian lays out the rows of non-square heightfields column by column.
+    pub heights: Vec<Vec<f32>>,
+    /// Corner of the grid with the lowest x and z
+    pub min: Vec2,
+    /// Extent of the grid along x and z
+    pub size: Vec2,
+}
+
+impl Heightfield {
+    pub fn resolution(&self) -> usize {
+        self.heights.len()
+    }
+
+    pub fn to_collider(&self) -> Collider {
+        let center = self.min + self.size / 2.0;
+        let heightfield = Collider::heightfield(self.heights.clone(), Vec3::new(self.size.x, 1.0, self.size.y));
+
+        // avian centres heightfields on their origin, so move it back over the chunk
+        Collider::compound(vec![(Vec3::new(center.x, 0.0, center.y), Quat::IDENTITY, heightfield)])
+    }
+
+    fn point(&self, x: usize, z: usize) -> Vec2 {
+        let cells = (self.resolution() - 1) as f32;
+        self.min + self.size * Vec2::new(x as f32, z as f32) / cells
+    }
+
+    /// Height of the surface above `point`, with every cell split into two triangles along the
+    /// diagonal from (x, z + 1) to (x + 1, z) like parry does
+    fn height_at(&self, point: Vec2) -> f32 {
+        let cells = self.resolution() - 1;
+        let grid = ((point - self.min) / self.size * cells as f32).clamp(Vec2::ZERO, Vec2::splat(cells as f32));
+        let x = (grid.x as usize).min(cells - 1);
+        let z = (grid.y as usize).min(cells - 1);
+        let (u, v) = (grid.x - x as f32, grid.y - z as f32);
+
+        let h00 = self.heights[x][z];
+        let h10 = self.heights[x + 1][z];
+        let h01 = self.heights[x][z + 1];
+        let h11 = self.heights[x + 1][z + 1];
+
+        if u + v <= 1.0 {
+            h00 + u * (h10 - h00) + v * (h01 - h00)
+        } else {
+            h11 + (1.0 - u) * (h01 - h11) + (1.0 - v) * (h10 - h11)
+        }
+    }
+}
+
+fn xz(vertex: Vec3) -> Vec2 {
+    Vec2::new(vertex.x, vertex.z)
+}
+
+/// Median of the non-zero steps between the ends of the edges along one axis
+fn median_step(triangles: &[[Vec3; 3]], axis: fn(Vec3) -> f32, min_step: f32) -> Option<f32> {
+    let mut steps: Vec<f32> = triangles.iter()
+        .flat_map(|[a, b, c]| [(a, b), (b, c), (c, a)])
+        .map(|(start, end)| (axis(*end) - axis(*start)).abs())
+        .filter(|step| *step > min_step)
+        .collect();
+
+    if steps.is_empty() {
+        return None;
+    }
+    let middle = steps.len() / 2;
+    Some(*steps.select_nth_unstable_by(middle, f32::total_cmp).1)
+}
+
+/// Resamples a chunk onto a regular grid of heights, if it is a single-valued surface over its XZ bounds
+/// that the grid reproduces to within `max_error`. Returns `None` for walls, overhangs, holes and
+/// anything else a heightfield can't represent.
+pub fn fit(vertices: &[Vec3], indices: &[[u32; 3]], max_error: f32) -> Option<Heightfield> {
+    let triangles: Vec<[Vec3; 3]> = indices.iter().map(|triangle| triangle.map(|i| vertices[i as usize])).collect();
+
+    let min = triangles.iter().flatten().copied().map(xz).reduce(Vec2::min)?;
+    let max = triangles.iter().flatten().copied().map(xz).reduce(Vec2::max)?;
+    let size = max - min;
+    if !(size.x > 0.0 && size.y > 0.0) {
+        return None;
+    }
+
+    // every triangle has to face the same way when seen from above, or the surface folds over itself
+    let projected_areas: Vec<f32> = triangles.iter().map(|[a, b, c]| (*b - *a).cross(*c - *a).y / 2.0).collect();
+    if !(projected_areas.iter().all(|area| *area > 0.0) || projected_areas.iter().all(|area| *area < 0.0)) {
+        return None;
+    }
+    // the triangles have to cover the bounds exactly once, without gaps or layers
+    let covered_area: f32 = projected_areas.iter().map(|area| area.abs()).sum();
+    if (covered_area - size.x * size.y).abs() > size.x * size.y * 0.001 {
+        return None;
+    }
+
+    let min_step = size.max_element() * 0.0001;
+    let step_x = median_step(&triangles, |vertex| vertex.x, min_step)?;
+    let step_z = median_step(&triangles, |vertex| vertex.z, min_step)?;
+    let resolution = ((size.x / step_x).round() as usize).max((size.y / step_z).round() as usize) + 1;
+    if resolution > MAX_RESOLUTION {
+        return None;
+    }
+
+    let mut heightfield = Heightfield {
+        heights: vec![vec![f32::NAN; resolution]; resolution],
+        min,
+        size,
+    };
+
+    // sample the triangles at every grid point they cover
+    let cells = (resolution - 1) as f32;
+    let epsilon = 0.0001;
+    for [a, b, c] in &triangles {
+        let (a2, b2, c2) = (xz(*a), xz(*b), xz(*c));
+        let lower = ((a2.min(b2).min(c2) - min) / size * cells).floor().max(Vec2::ZERO);
+        let upper = ((a2.max(b2).max(c2) - min) / size * cells).ceil().min(Vec2::splat(cells));
+        let area = (b2 - a2).perp_dot(c2 - a2);
+
+        for x in lower.x as usize..=upper.x as usize {
+            for z in lower.y as usize..=upper.y as usize {
+                let point = heightfield.point(x, z);
+                let u = (c2 - b2).perp_dot(point - b2) / area;
+                let v = (a2 - c2).perp_dot(point - c2) / area;
+                let w = 1.0 - u - v;
+                if u < -epsilon || v < -epsilon || w < -epsilon {
+                    continue;
+                }
+
+                let height = u * a.y + v * b.y + w * c.y;
+                let sample = &mut heightfield.heights[x][z];
+                if sample.is_nan() {
+                    *sample = height;
+                } else if (*sample - height).abs() > max_error {
+                    return None;
+                }
+            }
+        }
+    }
+
+    if heightfield.heights.iter().flatten().any(|height| height.is_nan()) {
+        return None;
+    }
+
+    // the grid only matches the source at its own points, so check the source's corners, edges and centres too
+    let fits = triangles.iter()
+        .flat_map(|[a, b, c]| [*a, *b, *c, (*a + *b) / 2.0, (*b + *c) / 2.0, (*c + *a) / 2.0, (*a + *b + *c) / 3.0])
+        .all(|point| (heightfield.height_at(xz(point)) - point.y).abs() <= max_error);
+
+    fits.then_some(heightfield)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `size` x `size` grid of unit quads starting at (-2, 3), split along the same diagonal as parry's heightfields
+    fn grid(size: u32, height: impl Fn(f32, f32) -> f32) -> (Vec<Vec3>, Vec<[u32; 3]>) {
+        let vertices = (0..=size)
+            .flat_map(|z| (0..=size).map(move |x| (x as f32 - 2.0, z as f32 + 3.0)))
+            .map(|(x, z)| Vec3::new(x, height(x, z), z))
+            .collect();
+        let indices = (0..size)
+            .flat_map(|z| (0..size).flat_map(move |x| {
+                let corner = z * (size + 1) + x;
+                [[corner, corner + size + 1, corner + 1], [corner + 1, corner + size + 1, corner + size + 2]]
+            }))
+            .collect();
+
+        (vertices, indices)
+    }
+
+    #[test]
+    fn heightfield_collider_matches_trimesh() {
+        let max_error = 0.01;
+        // uneven along both axes, so swapping rows and columns or cutting cells along the other diagonal shows
+        let (vertices, indices) = grid(6, |x, z| (x * 1.3).sin() * 2.0 + z * z * 0.1 + x * 0.5);
+        let heightfield = fit(&vertices, &indices, max_error).expect("a height grid fits");
+        assert_eq!(heightfield.resolution(), 7);
+
+        let heightfield_collider = heightfield.to_collider();
+        let trimesh_collider = Collider::trimesh(vertices, indices);
+        let mut rng = fastrand::Rng::with_seed(9);
+
+        for _ in 0..500 {
+            let origin = Vec3::new(rng.f32() * 5.8 - 1.9, 20.0, rng.f32() * 5.8 + 3.1);
+            let direction = Vec3::new((rng.f32() - 0.5) * 0.3, -1.0, (rng.f32() - 0.5) * 0.3).normalize();
+
+            let heightfield_hit = heightfield_collider.cast_ray(Vec3::ZERO, Quat::IDENTITY, origin, direction, 100.0, true);
+            let trimesh_hit = trimesh_collider.cast_ray(Vec3::ZERO, Quat::IDENTITY, origin, direction, 100.0, true);
+            let (heightfield_distance, trimesh_distance) = match (heightfield_hit, trimesh_hit) {
+                (Some((heightfield_distance, _)), Some((trimesh_distance, _))) => (heightfield_distance, trimesh_distance),
+                (None, None) => continue,
+                _ => panic!("only one collider was hit from {origin} along {direction}"),
+            };
+
+            let heightfield_point = origin + direction * heightfield_distance;
+            let trimesh_point = origin + direction * trimesh_distance;
+            assert!((heightfield_point.y - trimesh_point.y).abs() <= max_error, "heightfield hit {heightfield_point}, trimesh hit {trimesh_point}");
+        }
+    }
+
+    #[test]
+    fn non_height_grids_are_refused() {
+        let flat = |_: f32, _: f32| 0.0;
+
+        // cells cut along the other diagonal only fit where they are flat
+        let (vertices, _) = grid(2, |x, z| x * z);
+        let other_diagonal: Vec<[u32; 3]> = [0, 1, 3, 4].into_iter()
+            .flat_map(|corner| [[corner, corner + 3, corner + 4], [corner, corner + 4, corner + 1]])
+            .collect();
+        assert!(fit(&vertices, &other_diagonal, 0.01).is_none());
+
+        // a second layer above the grid
+        let (mut vertices, mut indices) = grid(2, flat);
+        let (upper_vertices, upper_indices) = grid(2, |_, _| 5.0);
+        let offset = vertices.len() as u32;
+        vertices.extend(upper_vertices);
+        indices.extend(upper_indices.into_iter().map(|triangle| triangle.map(|i| i + offset)));
+        assert!(fit(&vertices, &indices, 0.01).is_none());
+
+        // a hole in the middle
+        let (vertices, mut indices) = grid(3, flat);
+        indices.drain(8..10);
+        assert!(fit(&vertices, &indices, 0.01).is_none());
+
+        // one triangle folded under its neighbour
+        let (vertices, mut indices) = grid(2, flat);
+        indices[3].swap(1, 2);
+        assert!(fit(&vertices, &indices, 0.01).is_none());
+
+        // a vertical wall has no extent along z
+        let wall = vec![Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 1.0, 0.0)];
+        assert!(fit(&wall, &[[0, 1, 2], [2, 1, 3]], 0.01).is_none());
+
+        // while the same grid without any of that fits
+        let (vertices, indices) = grid(2, flat);
+        assert!(fit(&vertices, &indices, 0.01).is_some());
+    }
+}
@@ -0,0 +1,502 @@
+use bevy::math::bounding::{Aabb3d, BoundingVolume, RayCast3d};
+use bevy::math::{Dir3A, Vec3A};
+use bevy::prelude::Vec3;
+use std::collections::HashMap;
+use crate::collider_divider::{ChunkPos, Meshlet};
+use crate::collider_surface::SurfaceId;
+
+/// Items per BVH leaf
+const LEAF_SIZE: usize = 4;
+
+#[derive(Copy, Clone, Debug)]
+struct BvhNode {
+    aabb: Aabb3d,
+    // children at `first` and `first + 1` for inner nodes, or the items `first..first + count` for leaves
+    first: u32,
+    count: u32,
+}
+
+/// Bounding volume hierarchy over anything with an [`Aabb3d`], split at the median of the longest axis
+#[derive(Clone, Debug, Default)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    items: Vec<u32>,
+}
+
+impl Bvh {
+    pub fn new(aabbs: &[Aabb3d]) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::new(),
+            items: (0..aabbs.len() as u32).collect(),
+        };
+
+        if !aabbs.is_empty() {
+            bvh.nodes.push(BvhNode { aabb: aabbs[0], first: 0, count: 0 });
+            bvh.build(aabbs, 0, 0, aabbs.len());
+        }
+        bvh
+    }
+
+    fn build(&mut self, aabbs: &[Aabb3d], node: usize, start: usize, end: usize) {
+        let items = &mut self.items[start..end];
+        let aabb = items.iter().map(|item| aabbs[*item as usize]).reduce(|a, b| a.merge(&b)).unwrap();
+
+        if items.len() <= LEAF_SIZE {
+            self.nodes[node] = BvhNode { aabb, first: start as u32, count: items.len() as u32 };
+            return;
+        }
+
+        // splitting at the median always halves the items, even when their centres all coincide
+        let centres = items.iter().map(|item| aabbs[*item as usize].center());
+        let centre_min = centres.clone().fold(Vec3A::INFINITY, Vec3A::min);
+        let centre_max = centres.fold(Vec3A::NEG_INFINITY, Vec3A::max);
+        let extent = centre_max - centre_min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z { 0 } else if extent.y >= extent.z { 1 } else { 2 };
+
+        let mid = items.len() / 2;
+        items.select_nth_unstable_by(mid, |a, b| aabbs[*a as usize].center()[axis].total_cmp(&aabbs[*b as usize].center()[axis]));
+
+        let first = self.nodes.len();
+        self.nodes.push(self.nodes[node]);
+        self.nodes.push(self.nodes[node]);
+        self.nodes[node] = BvhNode { aabb, first: first as u32, count: 0 };
+        self.build(aabbs, first, start, start + mid);
+        self.build(aabbs, first + 1, start + mid, end);
+    }
+
+    /// Bounds of every item, or `None` if there are none
+    pub fn aabb(&self) -> Option<Aabb3d> {
+        self.nodes.first().map(|node| node.aabb)
+    }
+
+    /// Closest item along a ray, where `hit` returns how far along the ray an item is hit, if it is.
+    /// Items are only tested if their bounds are closer than the best hit so far.
+    pub fn cast_ray(&self, ray: &RayCast3d, mut hit: impl FnMut(usize) -> Option<f32>) -> Option<(usize, f32)> {
+        let mut best: Option<(usize, f32)> = None;
+        let mut stack: Vec<(usize, f32)> = self.aabb()
+            .and_then(|aabb| ray.aabb_intersection_at(&aabb))
+            .map(|distance| (0, distance))
+            .into_iter()
+            .collect();
+
+        while let Some((node, entry)) = stack.pop() {
+            if best.is_some_and(|(_, distance)| distance < entry) {
+                continue;
+            }
+
+            let node = self.nodes[node];
+            if node.count > 0 {
+                for item in &self.items[node.first as usize..(node.first + node.count) as usize] {
+                    if let Some(distance) = hit(*item as usize).filter(|distance| *distance <= ray.max) {
+                        if !best.is_some_and(|(_, best_distance)| best_distance <= distance) {
+                            best = Some((*item as usize, distance));
+                        }
+                    }
+                }
+                continue;
+            }
+
+            // visit the nearer child first, so the further one can often be skipped
+            let mut children: Vec<(usize, f32)> = (node.first as usize..node.first as usize + 2)
+                .filter_map(|child| Some((child, ray.aabb_intersection_at(&self.nodes[child].aabb)?)))
+                .collect();
+            children.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+            stack.extend(children);
+        }
+
+        best
+    }
+
+    /// Closest item to `point` within `max_distance`, where `distance` returns how far `point` is from an item
+    pub fn nearest(&self, point: Vec3, max_distance: f32, mut distance: impl FnMut(usize) -> f32) -> Option<(usize, f32)> {
+        let mut best: Option<(usize, f32)> = None;
+        let mut stack = if self.nodes.is_empty() { Vec::new() } else { vec![0] };
+
+        while let Some(node) = stack.pop() {
+            let node = self.nodes[node];
+            let bound = best.map_or(max_distance, |(_, distance)| distance);
+            if aabb_distance(&node.aabb, point) > bound {
+                continue;
+            }
+
+            if node.count > 0 {
+                for item in &self.items[node.first as usize..(node.first + node.count) as usize] {
+                    let item_distance = distance(*item as usize);
+                    if item_distance <= best.map_or(max_distance, |(_, distance)| distance) {
+                        best = Some((*item as usize, item_distance));
+                    }
+                }
+                continue;
+            }
+
+            // visit the nearer child first, so the further one can often be skipped
+            let first = node.first as usize;
+            let (near, far) = if aabb_distance(&self.nodes[first].aabb, point) <= aabb_distance(&self.nodes[first + 1].aabb, point) {
+                (first, first + 1)
+            } else {
+                (first + 1, first)
+            };
+            stack.push(far);
+            stack.push(near);
+        }
+
+        best
+    }
+}
+
+fn aabb_distance(aabb: &Aabb3d, point: Vec3) -> f32 {
+    Vec3::from(aabb.closest_point(point)).distance(point)
+}
+
+pub(crate) fn triangle_aabb(triangle: [Vec3; 3]) -> Aabb3d {
+    Aabb3d {
+        min: Vec3A::from(triangle[0].min(triangle[1]).min(triangle[2])),
+        max: Vec3A::from(triangle[0].max(triangle[1]).max(triangle[2])),
+    }
+}
+
+fn triangle(meshlet: &Meshlet, index: usize) -> [Vec3; 3] {
+    meshlet.indices[index].map(|i| meshlet.vertices[i as usize])
+}
+
+/// BVH over the full detail triangles of a meshlet
+pub fn meshlet_bvh(meshlet: &Meshlet) -> Bvh {
+    let aabbs: Vec<Aabb3d> = (0..meshlet.indices.len()).map(|index| triangle_aabb(triangle(meshlet, index))).collect();
+    Bvh::new(&aabbs)
+}
+
+/// How far outside a triangle, as a fraction of its edges, a ray still counts as hitting it
+const BARYCENTRIC_SLACK: f32 = 1e-5;
+
+/// Distance along a ray to where it hits a triangle, from either side
+fn ray_triangle(origin: Vec3, direction: Vec3, [a, b, c]: [Vec3; 3]) -> Option<f32> {
+    let ab = b - a;
+    let ac = c - a;
+    let p = direction.cross(ac);
+    let determinant = ab.dot(p);
+    if determinant.abs() < f32::EPSILON * ab.length() * ac.length() {
+        return None;
+    }
+
+    let ao = origin - a;
+    let u = ao.dot(p) / determinant;
+    let q = ao.cross(ab);
+    let v = direction.dot(q) / determinant;
+    let distance = ac.dot(q) / determinant;
+
+    // triangles sharing an edge each round it a little differently, so without some slack a ray can slip between them
+    let slack = -BARYCENTRIC_SLACK;
+    (u >= slack && v >= slack && u + v <= 1.0 - slack && distance >= 0.0).then_some(distance)
+}
+
+/// Closest point of a triangle to `point`, from Ericson's Real-Time Collision Detection
+pub(crate) fn closest_point_on_triangle(point: Vec3, [a, b, c]: [Vec3; 3]) -> Vec3 {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = point - a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+
+    let bp = point - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = point - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let denominator = 1.0 / (va + vb + vc);
+    a + ab * (vb * denominator) + ac * (vc * denominator)
+}
+
+/// Where a query met the triangles of a chunk, in the space the meshlets are stored in
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TriangleHit {
+    pub chunk_pos: ChunkPos,
+    /// Index of the triangle in the chunk's full detail `indices`
+    pub triangle: usize,
+    pub surface: SurfaceId,
+    pub point: Vec3,
+    /// Face normal, pointing back towards the ray for ray casts like parry's trimeshes do
+    pub normal: Vec3,
+    /// How far along the ray, or how far from the queried point
+    pub distance: f32,
+}
+
+fn triangle_hit(meshlet: &Meshlet, index: usize, point: Vec3, distance: f32, facing: Vec3) -> TriangleHit {
+    let [a, b, c] = triangle(meshlet, index);
+    let normal = (b - a).cross(c - a).normalize_or_zero();
+
+    TriangleHit {
+        chunk_pos: meshlet.chunk_pos,
+        triangle: index,
+        surface: meshlet.surfaces.get(index).copied().unwrap_or_default(),
+        point,
+        normal: if normal.dot(facing) < 0.0 { -normal } else { normal },
+        distance,
+    }
+}
+
+/// Ray casts and nearest-surface queries over the full detail triangles of every chunk of a split mesh,
+/// whether or not the chunk currently has a collider.
+/// The queries take the meshlets the BVHs were built from, and everything is in the meshlets' space.
+#[derive(Clone, Debug, Default)]
+pub struct ChunkQuery {
+    chunks: HashMap<ChunkPos, Bvh>,
+    // the chunks in `chunk_order`, by their bounds
+    chunk_bvh: Bvh,
+    chunk_order: Vec<ChunkPos>,
+}
+
+impl ChunkQuery {
+    pub fn new(meshlets: &HashMap<ChunkPos, Meshlet>) -> Self {
+        let mut query = Self::default();
+        query.update(meshlets, meshlets.keys().copied());
+        query
+    }
+
+    /// Rebuilds the BVHs of chunks whose meshlets changed or were removed
+    pub fn update(&mut self, meshlets: &HashMap<ChunkPos, Meshlet>, changed: impl IntoIterator<Item = ChunkPos>) {
+        for chunk_pos in changed {
+            match meshlets.get(&chunk_pos) {
+                Some(meshlet) if !meshlet.indices.is_empty() => {
+                    self.chunks.insert(chunk_pos, meshlet_bvh(meshlet));
+                },
+                _ => {
+                    self.chunks.remove(&chunk_pos);
+                },
+            }
+        }
+
+        self.chunk_order = self.chunks.keys().copied().collect();
+        let aabbs: Vec<Aabb3d> = self.chunk_order.iter().map(|chunk_pos| self.chunks[chunk_pos].aabb().unwrap()).collect();
+        self.chunk_bvh = Bvh::new(&aabbs);
+    }
+
+    /// Bounds of every chunk, or `None` if there are none
+    pub fn aabb(&self) -> Option<Aabb3d> {
+        self.chunk_bvh.aabb()
+    }
+
+    /// First triangle a ray hits within `max_distance`, from either side.
+    /// `direction` doesn't need to be normalized, distances are measured in multiples of it.
+    pub fn cast_ray(&self, meshlets: &HashMap<ChunkPos, Meshlet>, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<TriangleHit> {
+        let length = direction.length();
+        let ray = RayCast3d::new(origin, Dir3A::new(direction.into()).ok()?, max_distance * length);
+        let unit_direction = direction / length;
+
+        let mut best: Option<(ChunkPos, usize, f32)> = None;
+        self.chunk_bvh.cast_ray(&ray, |chunk| {
+            let chunk_pos = self.chunk_order[chunk];
+            let meshlet = meshlets.get(&chunk_pos)?;
+            let (index, distance) = self.chunks[&chunk_pos].cast_ray(&ray, |index| ray_triangle(origin, unit_direction, triangle(meshlet, index)))?;
+            if !best.is_some_and(|(_, _, best_distance)| best_distance <= distance) {
+                best = Some((chunk_pos, index, distance));
+            }
+            Some(distance)
+        });
+
+        let (chunk_pos, index, distance) = best?;
+        let point = origin + unit_direction * distance;
+        Some(triangle_hit(&meshlets[&chunk_pos], index, point, distance / length, -unit_direction))
+    }
+
+    /// Closest point on any triangle within `max_distance` of `point`
+    pub fn project_point(&self, meshlets: &HashMap<ChunkPos, Meshlet>, point: Vec3, max_distance: f32) -> Option<TriangleHit> {
+        let mut best: Option<(ChunkPos, usize, Vec3)> = None;
+        let mut best_distance = max_distance;
+
+        self.chunk_bvh.nearest(point, max_distance, |chunk| {
+            let chunk_pos = self.chunk_order[chunk];
+            let Some(meshlet) = meshlets.get(&chunk_pos) else { return f32::INFINITY };
+
+            let mut closest = Vec3::NAN;
+            let found = self.chunks[&chunk_pos].nearest(point, best_distance, |index| {
+                let candidate = closest_point_on_triangle(point, triangle(meshlet, index));
+                let distance = candidate.distance(point);
+                if distance <= best_distance {
+                    closest = candidate;
+                    best_distance = distance;
+                }
+                distance
+            });
+
+            match found {
+                Some((index, distance)) => {
+                    best = Some((chunk_pos, index, closest));
+                    distance
+                },
+                None => f32::INFINITY,
+            }
+        });
+
+        let (chunk_pos, index, closest) = best?;
+        Some(triangle_hit(&meshlets[&chunk_pos], index, closest, best_distance, point - closest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collider_divider::{try_split_meshlets, SplitSettings};
+    use bevy::prelude::Mesh;
+    use bevy::render::{mesh::{Indices, PrimitiveTopology}, render_asset::RenderAssetUsages};
+
+    fn random_terrain(rng: &mut fastrand::Rng, size: u32) -> Mesh {
+        let vertices: Vec<[f32; 3]> = (0..=size)
+            .flat_map(|z| (0..=size).map(move |x| (x, z)))
+            .map(|(x, z)| [x as f32 * 2.0 - size as f32, rng.f32() * 6.0, z as f32 * 2.0 - size as f32])
+            .collect();
+        let indices: Vec<u32> = (0..size)
+            .flat_map(|z| (0..size).map(move |x| z * (size + 1) + x))
+            .flat_map(|i| [i, i + size + 1, i + 1, i + 1, i + size + 1, i + size + 2])
+            .collect();
+
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default());
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
+        mesh.insert_indices(Indices::U32(indices));
+        mesh
+    }
+
+    fn all_triangles(meshlets: &HashMap<ChunkPos, Meshlet>) -> impl Iterator<Item = (ChunkPos, usize, [Vec3; 3])> + '_ {
+        meshlets.values().flat_map(|meshlet| (0..meshlet.indices.len()).map(move |index| (meshlet.chunk_pos, index, triangle(meshlet, index))))
+    }
+
+    #[test]
+    fn queries_match_brute_force() {
+        let mut rng = fastrand::Rng::with_seed(21);
+        let mesh = random_terrain(&mut rng, 24);
+        let meshlets: HashMap<ChunkPos, Meshlet> = try_split_meshlets(&mesh, &SplitSettings::new(5.0)).unwrap()
+            .into_iter()
+            .map(|meshlet| (meshlet.chunk_pos, meshlet))
+            .collect();
+        let query = ChunkQuery::new(&meshlets);
+
+        for _ in 0..200 {
+            let origin = Vec3::new(rng.f32() * 60.0 - 30.0, rng.f32() * 20.0 - 5.0, rng.f32() * 60.0 - 30.0);
+            let direction = Vec3::new(rng.f32() * 2.0 - 1.0, rng.f32() * 2.0 - 1.0, rng.f32() * 2.0 - 1.0) * 3.0;
+
+            let expected = all_triangles(&meshlets)
+                .filter_map(|(_, _, triangle)| ray_triangle(origin, direction.normalize(), triangle))
+                .filter(|distance| *distance <= 10.0 * direction.length())
+                .min_by(f32::total_cmp);
+            let hit = query.cast_ray(&meshlets, origin, direction, 10.0);
+            assert_eq!(hit.is_some(), expected.is_some());
+            if let (Some(hit), Some(expected)) = (hit, expected) {
+                assert!((hit.distance * direction.length() - expected).abs() < 1e-3);
+                assert!(hit.normal.dot(direction) <= 0.0);
+            }
+
+            let expected = all_triangles(&meshlets)
+                .map(|(_, _, triangle)| closest_point_on_triangle(origin, triangle).distance(origin))
+                .min_by(f32::total_cmp)
+                .unwrap();
+            let projection = query.project_point(&meshlets, origin, f32::INFINITY).unwrap();
+            assert!((projection.distance - expected).abs() < 1e-4);
+            assert!((projection.point.distance(origin) - expected).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn queries_match_avian_trimeshes() {
+        use avian3d::collision::collider::Collider;
+        use bevy::prelude::Quat;
+
+        let mut rng = fastrand::Rng::with_seed(17);
+        let mesh = random_terrain(&mut rng, 12);
+        let meshlets: HashMap<ChunkPos, Meshlet> = try_split_meshlets(&mesh, &SplitSettings::new(4.0)).unwrap()
+            .into_iter()
+            .map(|meshlet| (meshlet.chunk_pos, meshlet))
+            .collect();
+        let query = ChunkQuery::new(&meshlets);
+        // what the chunks would be hit at once they are loaded as colliders
+        let colliders: Vec<Collider> = meshlets.values().map(|meshlet| Collider::trimesh(meshlet.vertices.clone(), meshlet.indices.clone())).collect();
+
+        // aim at random points, and exactly at vertices and at the middle of edges triangles share -
+        // on the open border of the mesh a ray grazing the last triangle may go either way
+        let positions: Vec<Vec3> = mesh.attribute(Mesh::ATTRIBUTE_POSITION).unwrap().as_float3().unwrap().iter().map(|position| Vec3::from(*position)).collect();
+        let indices: Vec<usize> = mesh.indices().unwrap().iter().collect();
+        let shared = |point: &Vec3| point.x.abs() < 12.0 && point.z.abs() < 12.0;
+        let mut targets: Vec<Vec3> = (0..100).map(|_| Vec3::new(rng.f32() * 24.0 - 12.0, rng.f32() * 6.0, rng.f32() * 24.0 - 12.0)).collect();
+        targets.extend(positions.iter().copied().filter(shared).step_by(5));
+        targets.extend(indices.chunks_exact(3).step_by(7)
+            .flat_map(|triangle| [(triangle[0], triangle[1]), (triangle[1], triangle[2]), (triangle[2], triangle[0])])
+            .map(|(a, b)| (positions[a] + positions[b]) / 2.0)
+            .filter(shared));
+
+        let avian_ray = |origin: Vec3, target: Vec3| colliders.iter()
+            .filter_map(|collider| collider.cast_ray(Vec3::ZERO, Quat::IDENTITY, origin, (target - origin).normalize(), 100.0, true))
+            .map(|(distance, _)| distance)
+            .min_by(f32::total_cmp);
+
+        for target in targets {
+            for origin in [target + Vec3::Y * 20.0, target + Vec3::new(rng.f32() * 8.0 - 4.0, rng.f32() * 10.0 + 1.0, rng.f32() * 8.0 - 4.0)] {
+                let hit = query.cast_ray(&meshlets, origin, (target - origin).normalize(), 100.0);
+
+                match (hit, avian_ray(origin, target)) {
+                    (Some(hit), Some(expected)) if (hit.distance - expected).abs() < 1e-3 => {},
+                    (None, None) => {},
+                    (None, Some(expected)) => panic!("avian hits {expected} but the query misses from {origin} towards {target}"),
+                    // parry can let a ray through exactly where its triangles meet, so compare with rays just beside it
+                    (Some(hit), expected) => {
+                        let beside: Vec<Option<f32>> = [Vec3::X, Vec3::NEG_X, Vec3::Z, Vec3::NEG_Z].iter().map(|offset| avian_ray(origin, target + *offset * 1e-3)).collect();
+                        assert!(
+                            expected.is_none_or(|expected| expected > hit.distance) && beside.iter().flatten().any(|beside| (hit.distance - beside).abs() < 1e-2),
+                            "hit at {} but avian hits {expected:?}, and {beside:?} beside it, from {origin} towards {target}", hit.distance
+                        );
+                    },
+                }
+
+                let expected = colliders.iter()
+                    .map(|collider| collider.project_point(Vec3::ZERO, Quat::IDENTITY, origin, true).0.distance(origin))
+                    .min_by(f32::total_cmp)
+                    .unwrap();
+                let projection = query.project_point(&meshlets, origin, f32::INFINITY).unwrap();
+                assert!((projection.distance - expected).abs() < 1e-3, "closest point at {} instead of {expected} from {origin}", projection.distance);
+            }
+        }
+    }
+
+    #[test]
+    fn straight_down_ray_finds_height() {
+        let mut rng = fastrand::Rng::with_seed(3);
+        let mesh = random_terrain(&mut rng, 8);
+        let meshlets: HashMap<ChunkPos, Meshlet> = try_split_meshlets(&mesh, &SplitSettings::new(3.0)).unwrap()
+            .into_iter()
+            .map(|meshlet| (meshlet.chunk_pos, meshlet))
+            .collect();
+        let query = ChunkQuery::new(&meshlets);
+
+        // the middle of the first grid square lies on the edge its two triangles share, between vertices 1 and 9
+        let positions = mesh.attribute(Mesh::ATTRIBUTE_POSITION).unwrap().as_float3().unwrap();
+        let hit = query.cast_ray(&meshlets, Vec3::new(-7.0, 100.0, -7.0), Vec3::NEG_Y, f32::INFINITY).unwrap();
+        assert!((hit.point.y - (positions[1][1] + positions[9][1]) / 2.0).abs() < 1e-4);
+        assert!(hit.normal.y > 0.0);
+
+        assert!(query.cast_ray(&meshlets, Vec3::new(50.0, 100.0, 0.0), Vec3::NEG_Y, f32::INFINITY).is_none());
+    }
+}
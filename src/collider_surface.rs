@@ -0,0 +1,126 @@
+use bevy::prelude::{Component, Mesh, Vec3};
+use bevy::render::mesh::{MeshVertexAttribute, VertexAttributeValues};
+use bevy::render::render_resource::VertexFormat;
+use std::sync::Arc;
+use crate::collider_query::{closest_point_on_triangle, triangle_aabb, Bvh};
+
+/// What a triangle is made of, so gameplay can tell grass from rock from ice.
+/// 0 is a surface without a material, any other value is the glTF material index plus one
+/// unless the mesh sets its own surfaces with [`ATTRIBUTE_SURFACE`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SurfaceId(pub u32);
+
+/// Surface of each vertex, stored as `_SURFACE` unsigned ints in glTF files.
+/// A triangle takes the surface of its first vertex.
+pub const ATTRIBUTE_SURFACE: MeshVertexAttribute = MeshVertexAttribute::new("Vertex_Surface", 988_540_917, VertexFormat::Uint32);
+
+/// Surface of a glTF primitive using the material at `material_index`
+pub fn material_surface(material_index: Option<usize>) -> SurfaceId {
+    SurfaceId(material_index.map_or(0, |index| index as u32 + 1))
+}
+
+/// Gives every vertex of `mesh` the surface of its material, unless it already has [`ATTRIBUTE_SURFACE`]
+pub fn insert_material_surface(mesh: &mut Mesh, surface: SurfaceId) {
+    if !mesh.contains_attribute(ATTRIBUTE_SURFACE) {
+        let vertex_count = mesh.count_vertices();
+        mesh.insert_attribute(ATTRIBUTE_SURFACE, vec![surface.0; vertex_count]);
+    }
+}
+
+/// Surface of every vertex of `mesh`, or `None` if it has no [`ATTRIBUTE_SURFACE`]
+pub(crate) fn vertex_surfaces(mesh: &Mesh) -> Option<&[u32]> {
+    match mesh.attribute(ATTRIBUTE_SURFACE) {
+        Some(VertexAttributeValues::Uint32(surfaces)) => Some(surfaces),
+        _ => None,
+    }
+}
+
+/// Surface of every triangle of a decimated mesh, taken from the source triangle closest to its centre that faces the same way,
+/// so the two sides of a wall thinner than the decimation error keep their own surfaces
+pub(crate) fn transfer(source_vertices: &[Vec3], source_indices: &[[u32; 3]], source_surfaces: &[SurfaceId], vertices: &[Vec3], indices: &[[u32; 3]]) -> Vec<SurfaceId> {
+    // nothing to choose between, so skip the search
+    if let Some(surface) = uniform(source_surfaces) {
+        return vec![surface; indices.len()];
+    }
+
+    let source_triangles: Vec<[Vec3; 3]> = source_indices.iter().map(|triangle| triangle.map(|i| source_vertices[i as usize])).collect();
+    let bvh = Bvh::new(&source_triangles.iter().map(|triangle| triangle_aabb(*triangle)).collect::<Vec<_>>());
+    let normal = |[a, b, c]: [Vec3; 3]| (b - a).cross(c - a);
+
+    indices.iter()
+        .map(|triangle| {
+            let triangle = triangle.map(|i| vertices[i as usize]);
+            let centre = triangle.iter().sum::<Vec3>() / 3.0;
+            let facing = normal(triangle);
+            let distance = |source: usize| closest_point_on_triangle(centre, source_triangles[source]).distance(centre);
+
+            // source triangles facing away only count if none face the same way, e.g. for slivers without a clear normal
+            bvh.nearest(centre, f32::MAX, |source| if normal(source_triangles[source]).dot(facing) > 0.0 { distance(source) } else { f32::INFINITY })
+                .or_else(|| bvh.nearest(centre, f32::INFINITY, distance))
+                .map_or(SurfaceId::default(), |(source, _)| source_surfaces[source])
+        })
+        .collect()
+}
+
+/// The surface all of `surfaces` share, if they do
+pub(crate) fn uniform(surfaces: &[SurfaceId]) -> Option<SurfaceId> {
+    let first = *surfaces.first()?;
+    surfaces.iter().all(|surface| *surface == first).then_some(first)
+}
+
+/// Surface of every triangle of a chunk collider, looked up by the feature ID of a hit on its shape
+#[derive(Component, Clone, Debug, PartialEq, Eq)]
+pub enum SurfaceLookup {
+    /// The whole collider is made of one surface, as heightfields always are
+    Uniform(SurfaceId),
+    /// One surface per trimesh triangle, shared between every entity using the collider
+    Triangles(Arc<[SurfaceId]>),
+}
+
+impl SurfaceLookup {
+    pub fn new(surfaces: &[SurfaceId]) -> Self {
+        match uniform(surfaces) {
+            Some(surface) => SurfaceLookup::Uniform(surface),
+            None => SurfaceLookup::Triangles(surfaces.into()),
+        }
+    }
+
+    /// Surface of the face parry reports as `feature_id`.
+    /// Trimeshes number back faces after the front faces, so those wrap around to the same triangle.
+    pub fn surface(&self, feature_id: u32) -> SurfaceId {
+        match self {
+            SurfaceLookup::Uniform(surface) => *surface,
+            SurfaceLookup::Triangles(surfaces) if surfaces.is_empty() => SurfaceId::default(),
+            SurfaceLookup::Triangles(surfaces) => surfaces[feature_id as usize % surfaces.len()],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transfer_takes_the_closest_triangle_facing_the_same_way() {
+        let source_vertices = vec![
+            // grass in a big floor triangle, meeting rock in a small one along its long edge
+            Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 4.0), Vec3::new(4.0, 0.0, 0.0),
+            Vec3::new(3.0, 0.0, 1.0), Vec3::new(4.0, 0.0, 1.0),
+            // the two sides of a wall a tenth thick, facing -z and +z
+            Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 0.1), Vec3::new(1.0, 0.0, 0.1), Vec3::new(0.0, 1.0, 0.1),
+        ];
+        let source_indices = vec![[0, 1, 2], [2, 3, 4], [5, 6, 7], [8, 9, 10]];
+        let source_surfaces = vec![SurfaceId(1), SurfaceId(2), SurfaceId(3), SurfaceId(4)];
+
+        let vertices = vec![
+            // a floor triangle in the grass, whose centre is closer to the centre of the rock triangle
+            Vec3::new(2.7, 0.0, 0.3), Vec3::new(3.0, 0.0, 0.9), Vec3::new(3.3, 0.0, 0.3),
+            // the front of the wall, moved closer to its back by decimation
+            Vec3::new(0.1, 0.1, 0.06), Vec3::new(0.1, 0.6, 0.06), Vec3::new(0.6, 0.1, 0.06),
+        ];
+        let indices = vec![[0, 1, 2], [3, 4, 5]];
+
+        assert_eq!(transfer(&source_vertices, &source_indices, &source_surfaces, &vertices, &indices), vec![SurfaceId(1), SurfaceId(3)]);
+    }
+}
@@ -0,0 +1,32 @@
+pub mod chunk_grid;
+pub mod collider_cache;
+pub mod collider_decimator;
+pub mod collider_divider;
+pub mod collider_heightfield;
+pub mod collider_query;
+pub mod collider_surface;
+pub mod map_scene;
+pub mod render_cache;
+
+use collider_divider::{BoundaryMode, ChunkTreeSettings, LodLevel, SplitSettings};
+
+/// Edge length of the chunks the map colliders are split into
+pub const CHUNK_SIZE: f32 = 15.0;
+/// Decimated colliders used for chunks further away from a loader
+pub const MAP_LODS: &[LodLevel] = &[
+    LodLevel { target_ratio: 0.25, max_error: 0.05 },
+    LodLevel { target_ratio: 0.25, max_error: 0.25 },
+];
+/// How the map is split into subcolliders, shared by the game and `bake-colliders`
+pub const MAP_SPLIT_SETTINGS: SplitSettings = SplitSettings::new(CHUNK_SIZE)
+    .with_boundary_mode(BoundaryMode::Clip)
+    .with_cleanup(0.001)
+    .with_lods(MAP_LODS)
+    .with_heightfields(0.01);
+/// How the map's chunks are merged where they have few triangles, so every collider costs about the same
+pub const MAP_CHUNK_TREE: ChunkTreeSettings = ChunkTreeSettings {
+    max_triangles: 2048,
+    max_level: 4,
+};
+/// Where baked subcolliders are stored, relative to the working directory
+pub const COLLIDER_CACHE_DIR: &str = "assets/collider_cache";
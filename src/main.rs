@@ -2,7 +2,6 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 mod character_controller;
-mod collider_divider;
 
 use bevy::{
     window::{WindowTheme, WindowMode, PresentMode, PrimaryWindow, CursorGrabMode, WindowResized},
@@ -10,15 +9,20 @@ use bevy::{
     prelude::*,
     diagnostic::LogDiagnosticsPlugin,
     core_pipeline::{bloom::BloomSettings, tonemapping::Tonemapping, motion_blur::{MotionBlur, MotionBlurBundle}, auto_exposure::{AutoExposurePlugin, AutoExposureSettings}, dof::{DepthOfFieldMode, DepthOfFieldSettings}},
-    render::{camera::Viewport, view::RenderLayers},
+    render::{camera::Viewport, view::RenderLayers, render_asset::RenderAssetUsages},
     asset::LoadState,
+    gltf::GltfPlugin,
+    tasks::{block_on, poll_once, AsyncComputeTaskPool, IoTaskPool, Task},
+    scene::{scene_spawner_system, SceneInstanceReady},
     pbr::{VolumetricFogSettings, VolumetricLight, ShadowFilteringMethod, CascadeShadowConfigBuilder, NotShadowCaster},
 };
 use avian3d::{math::*, prelude::*};
 use winit::window::Icon;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use itertools::Itertools;
 use character_controller::*;
-
-const CHUNK_SIZE: f32 = 30.0;
+use voyage_abeon::{chunk_grid::ChunkGrid, collider_cache, collider_divider::{self, ChunkColliders, ChunkTree, Meshlet, RegionEdit, SplitError}, collider_query::{ChunkQuery, TriangleHit}, collider_surface, map_scene, render_cache, COLLIDER_CACHE_DIR, MAP_CHUNK_TREE, MAP_SPLIT_SETTINGS};
 
 #[derive(Resource)]
 struct Keybinds {
@@ -27,19 +31,271 @@ struct Keybinds {
 
 #[derive(Component)]
 struct Subcollider {
-    colliders: Vec<(collider_divider::ChunkPos, Collider)>,
-    chunk_size: f32,
-    active_colliders: Vec<Entity>,
+    // every level of detail of each leaf of `tree`, full detail first
+    colliders: HashMap<collider_divider::ChunkPos, ChunkColliders>,
+    // the triangles the colliders were built from, kept so edits can rebuild single chunks
+    meshlets: HashMap<collider_divider::ChunkPos, Meshlet>,
+    // BVHs over `meshlets`, for queries that don't need the chunk to be loaded
+    query: ChunkQuery,
+    // the grid the map was split on, following the map's transform
+    grid: ChunkGrid,
+    // how the grid chunks are merged into the chunks everything else is keyed by
+    tree: ChunkTree,
+    // chunks that currently have a collider entity, with the level of detail it uses
+    active_colliders: HashMap<collider_divider::ChunkPos, (usize, Entity)>,
 }
 
 impl Subcollider {
-    pub fn new(colliders: Vec<(collider_divider::ChunkPos, Collider)>, chunk_size: f32) -> Self {
+    pub fn new(meshlets: Vec<Meshlet>, colliders: Vec<(collider_divider::ChunkPos, ChunkColliders)>, grid: ChunkGrid, tree: ChunkTree) -> Self {
+        let meshlets = meshlets.into_iter().map(|meshlet| (meshlet.chunk_pos, meshlet)).collect();
+
         Self {
-            colliders: colliders,
-            chunk_size: chunk_size,
-            active_colliders: Vec::new()
+            colliders: colliders.into_iter().collect(),
+            query: ChunkQuery::new(&meshlets),
+            meshlets: meshlets,
+            grid: grid,
+            tree: tree,
+            active_colliders: HashMap::new()
         }
     }
+
+    /// Replaces the triangles in a region, rebuilding only the chunks it touches.
+    /// Returns the chunks that changed, which may have lost all of their geometry.
+    pub fn edit_region(&mut self, edit: &RegionEdit) -> Result<Vec<collider_divider::ChunkPos>, SplitError> {
+        let rebuilt = collider_divider::try_resplit_region(&self.meshlets, &self.tree, edit, &MAP_SPLIT_SETTINGS)?;
+
+        let changed: Vec<collider_divider::ChunkPos> = rebuilt.into_iter()
+            .map(|(chunk_pos, meshlet)| {
+                match meshlet {
+                    Some(meshlet) => {
+                        self.colliders.insert(chunk_pos, meshlet.to_lod_colliders());
+                        self.meshlets.insert(chunk_pos, meshlet);
+                    },
+                    None => {
+                        self.colliders.remove(&chunk_pos);
+                        self.meshlets.remove(&chunk_pos);
+                    },
+                }
+                chunk_pos
+            })
+            .collect();
+
+        self.query.update(&self.meshlets, changed.iter().copied());
+        Ok(changed)
+    }
+
+    /// Every level of detail of a single chunk, if it has any geometry
+    pub fn chunk(&self, chunk_pos: collider_divider::ChunkPos) -> Option<&[(Collider, collider_surface::SurfaceLookup)]> {
+        self.colliders.get(&chunk_pos).map(Vec::as_slice)
+    }
+
+    /// Every chunk with geometry covering some grid chunk between `min` and `max`, both included
+    pub fn chunks_in_rect(&self, min: collider_divider::ChunkPos, max: collider_divider::ChunkPos) -> impl Iterator<Item = (collider_divider::ChunkPos, &[(Collider, collider_surface::SurfaceLookup)])> {
+        // without vertical layers every chunk is in layer 0
+        let (min_y, max_y) = if self.grid.cell_height.is_some() { (min.y, max.y) } else { (0, 0) };
+
+        (min.x..=max.x)
+            .flat_map(move |x| (min_y..=max_y).flat_map(move |y| (min.z..=max.z).map(move |z| collider_divider::ChunkPos { x, y, z })))
+            .map(|chunk_pos| self.tree.leaf(chunk_pos))
+            .unique()
+            .filter_map(|chunk_pos| Some((chunk_pos, self.chunk(chunk_pos)?)))
+    }
+
+    /// Every chunk with geometry at most `radius` rings of grid chunks away from `center`, with how many rings away it is
+    pub fn chunks_in_radius(&self, center: collider_divider::ChunkPos, radius: i32) -> impl Iterator<Item = (collider_divider::ChunkPos, i32, &[(Collider, collider_surface::SurfaceLookup)])> {
+        let min = collider_divider::ChunkPos { x: center.x - radius, y: center.y - radius, z: center.z - radius };
+        let max = collider_divider::ChunkPos { x: center.x + radius, y: center.y + radius, z: center.z + radius };
+
+        self.chunks_in_rect(min, max).map(move |(chunk_pos, colliders)| (chunk_pos, self.tree.ring(chunk_pos, center), colliders))
+    }
+
+    /// First point of the map a world space ray hits within `max_distance`, whether its chunk is loaded or not.
+    /// Runs on the full detail triangles, so it agrees with Avian's ray casts on chunks loaded at full detail,
+    /// and with coarser levels of detail or heightfields up to their error.
+    pub fn cast_ray(&self, origin: Vec3, direction: Dir3, max_distance: f32) -> Option<TriangleHit> {
+        let local_origin = self.grid.world_to_local(origin);
+        // not normalized, so distances along it stay in world units
+        let local_direction = self.grid.transform.affine().inverse().transform_vector3(*direction);
+
+        let hit = self.query.cast_ray(&self.meshlets, local_origin, local_direction, max_distance)?;
+        Some(self.hit_to_world(hit, hit.distance))
+    }
+
+    /// Closest point of the map within `max_distance` of a world space point, whether its chunk is loaded or not.
+    /// Searches in the map's space, so it is exact as long as the map is scaled the same along every axis.
+    pub fn project_point(&self, point: Vec3, max_distance: f32) -> Option<TriangleHit> {
+        let min_scale = self.grid.transform.compute_transform().scale.abs().min_element();
+        let hit = self.query.project_point(&self.meshlets, self.grid.world_to_local(point), max_distance / min_scale)?;
+
+        let distance = self.grid.local_to_world(hit.point).distance(point);
+        (distance <= max_distance).then(|| self.hit_to_world(hit, distance))
+    }
+
+    /// Height of the highest point of the map above or below world (`x`, `z`), whether its chunk is loaded or not
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        // start the ray above the whole map, wherever its transform put it
+        let aabb = self.query.aabb()?;
+        let corner_heights = (0..8).map(|corner| {
+            let local = Vec3::select(BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0), aabb.max.into(), aabb.min.into());
+            self.grid.local_to_world(local).y
+        });
+        let (bottom, top) = corner_heights.fold((f32::INFINITY, f32::NEG_INFINITY), |(bottom, top), y| (bottom.min(y), top.max(y)));
+
+        let hit = self.cast_ray(Vec3::new(x, top + 1.0, z), Dir3::NEG_Y, top - bottom + 2.0)?;
+        Some(hit.point.y)
+    }
+
+    /// The chunk with geometry a body at world `point` would land on: the chunk it is in, or with vertical layers
+    /// the first chunk below it in its column that has any, as the layers it falls through on the way are empty
+    fn chunk_below(&self, point: Vec3) -> Option<collider_divider::ChunkPos> {
+        let chunk_pos = self.grid.world_to_chunk(point);
+        // without vertical layers a chunk is the whole column
+        let bottom = match self.grid.cell_height {
+            Some(_) => self.grid.local_to_chunk(self.query.aabb()?.min.into()).y,
+            None => chunk_pos.y,
+        };
+
+        (bottom..=chunk_pos.y).rev()
+            .map(|y| self.tree.leaf(collider_divider::ChunkPos { y, ..chunk_pos }))
+            .find(|leaf| self.colliders.contains_key(leaf))
+    }
+
+    /// Every chunk within some loader's unload radius, with the finest level of detail one of them needs
+    /// and whether any of them is close enough to load it. Loaders come with their world position and velocity.
+    fn wanted_chunks<'a>(&self, loaders: impl IntoIterator<Item = (&'a ChunkLoader, Vec3, Option<Vec3>)>) -> HashMap<collider_divider::ChunkPos, (usize, bool)> {
+        let mut wanted: HashMap<collider_divider::ChunkPos, (usize, bool)> = HashMap::new();
+
+        for (loader, position, velocity) in loaders {
+            let player_pos_rounded = self.grid.world_to_chunk(position);
+            let look_ahead_pos = loader.look_ahead.zip(velocity)
+                .map(|(seconds, velocity)| self.grid.world_to_chunk(position + velocity * seconds));
+
+            // rings of chunks around the loader (or where it is heading), getting coarser the further out they are
+            for center in std::iter::once(player_pos_rounded).chain(look_ahead_pos) {
+                for (chunk_pos, ring, lods) in self.chunks_in_radius(center, loader.unload_radius) {
+                    let lod = (ring as usize).min(lods.len() - 1);
+                    let wanted_lod = wanted.entry(chunk_pos).or_insert((lod, false));
+                    wanted_lod.0 = wanted_lod.0.min(lod);
+                    wanted_lod.1 |= ring <= loader.load_radius;
+                }
+            }
+        }
+
+        wanted
+    }
+
+    fn hit_to_world(&self, hit: TriangleHit, distance: f32) -> TriangleHit {
+        let normal_matrix = Mat3::from(self.grid.transform.affine().matrix3).inverse().transpose();
+
+        TriangleHit {
+            point: self.grid.local_to_world(hit.point),
+            normal: (normal_matrix * hit.normal).normalize_or_zero(),
+            distance: distance,
+            ..hit
+        }
+    }
+}
+
+/// A map primitive's mesh and its material
+type PrimitiveMesh = (Mesh, Handle<StandardMaterial>);
+
+/// Which primitives have a chunk file in each chunk, and how to read them
+struct RenderChunkFiles {
+    // the directory the chunk files are in, see `render_cache::session_dir`
+    dir: std::path::PathBuf,
+    chunks: HashMap<collider_divider::ChunkPos, Vec<usize>>,
+    // an empty mesh with the attributes of each split primitive, to read its chunk files into
+    primitives: Arc<Vec<PrimitiveMesh>>,
+}
+
+/// A map's render meshes, split on the same grid as its colliders so only the chunks near a loader are drawn
+#[derive(Component)]
+struct RenderChunks {
+    // chunk meshes are kept on disk, and only read and uploaded while the chunk is active
+    files: RenderChunkFiles,
+    // the grid the map was split on, following the map's transform
+    grid: ChunkGrid,
+    // chunks whose files are being read
+    loading: HashMap<collider_divider::ChunkPos, Task<Vec<PrimitiveMesh>>>,
+    // chunks that are currently drawn, with one entity per primitive
+    active_meshes: HashMap<collider_divider::ChunkPos, Vec<Entity>>,
+}
+
+impl RenderChunks {
+    pub fn new(files: RenderChunkFiles, grid: ChunkGrid) -> Self {
+        Self {
+            files,
+            grid: grid,
+            loading: HashMap::new(),
+            active_meshes: HashMap::new()
+        }
+    }
+
+    /// Reads the meshes of `chunk_pos` in the background
+    fn load(&self, chunk_pos: collider_divider::ChunkPos) -> Task<Vec<PrimitiveMesh>> {
+        let cache_dir = self.files.dir.clone();
+        let primitives = self.files.primitives.clone();
+        let chunk_primitives = self.files.chunks[&chunk_pos].clone();
+
+        IoTaskPool::get().spawn(async move {
+            chunk_primitives.into_iter()
+                .filter_map(|primitive| {
+                    let (template, material) = &primitives[primitive];
+                    let path = render_cache::chunk_path(&cache_dir, primitive, chunk_pos);
+                    match std::fs::read(&path).and_then(|bytes| render_cache::decode(template, &bytes)) {
+                        Ok(mut mesh) => {
+                            // the file stays on disk, so the asset doesn't need a CPU copy
+                            mesh.asset_usage = RenderAssetUsages::RENDER_WORLD;
+                            Some((mesh, material.clone()))
+                        },
+                        Err(err) => {
+                            error!("Failed to read render chunk {}: {}", path.display(), err);
+                            None
+                        },
+                    }
+                })
+                .collect()
+        })
+    }
+}
+
+/// Splitting of a map's render meshes that is still running in the background.
+/// Replaced by [`RenderChunks`] once it finishes.
+#[derive(Component)]
+struct RenderChunkTask(Task<RenderChunkFiles>);
+
+/// Sent when a map chunk gets a collider because a loader came close enough to it
+#[derive(Event, Clone, Copy, Debug)]
+pub struct ChunkActivated {
+    pub map: Entity,
+    pub chunk_pos: collider_divider::ChunkPos,
+    pub collider: Entity,
+}
+
+/// Sent when every loader moved away from a map chunk and its collider was despawned.
+/// `collider` no longer exists by the time this is read.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct ChunkDeactivated {
+    pub map: Entity,
+    pub chunk_pos: collider_divider::ChunkPos,
+    pub collider: Entity,
+}
+
+/// The map's glTF scene, spawned for its lights and cameras - its meshes are split into [`RenderChunks`]
+#[derive(Component)]
+struct MapSceneNodes;
+
+/// Splitting of a map's colliders that is still running in the background.
+/// Replaced by a [`Subcollider`] once it finishes.
+#[derive(Component)]
+struct SubcolliderTask(Task<Subcollider>);
+
+/// Replaces the triangles of `map` inside a box, see [`RegionEdit`].
+/// Only the colliders change, the render meshes stay as they are.
+#[derive(Event, Clone, Debug)]
+pub struct TerrainEdit {
+    pub map: Entity,
+    pub edit: RegionEdit,
 }
 
 fn setup_camera(mut commands: Commands, /*temporary */mut meshes: ResMut<Assets<Mesh>>,) {
@@ -62,7 +318,7 @@ fn setup_camera(mut commands: Commands, /*temporary */mut meshes: ResMut<Assets<
         ),
         Friction::ZERO.with_combine_rule(CoefficientCombine::Min),
         Restitution::ZERO.with_combine_rule(CoefficientCombine::Min),
-        ChunkLoader,
+        ChunkLoader::new(2, 3).with_look_ahead(0.5).with_render_distance(1000.0),
         GravityScale(4.0),
     )).with_children(|b| {b.spawn((
         Camera3dBundle {
@@ -151,7 +407,7 @@ fn setup_camera(mut commands: Commands, /*temporary */mut meshes: ResMut<Assets<
         },
         RigidBody::Dynamic,
         Collider::cuboid(1.0, 1.0, 1.0),
-        ChunkLoader
+        FreezeOutsideChunks::Sleep
     ));
 }
 
@@ -161,8 +417,57 @@ struct PlayerCamera;
 #[derive(Component)]
 struct PlayerRigidbody;
 
+/// Keeps the map colliders around it loaded, and optionally the map's render meshes too.
+/// Each ring of chunks further out from the loader uses the next coarser level of detail.
+#[derive(Component)]
+struct ChunkLoader {
+    load_radius: i32,       // rings of chunks around the loader that get a collider
+    unload_radius: i32,     // loaded chunks stay until the loader is further away than this, so crossing a border back and forth doesn't swap them
+    look_ahead: Option<f32>,    // also loads around where the loader will be this many seconds from now
+    render_distance: Option<f32>,   // world distance up to which chunks are drawn, usually where the fog hides everything
+}
+
+impl ChunkLoader {
+    pub fn new(load_radius: i32, unload_radius: i32) -> Self {
+        Self {
+            load_radius: load_radius,
+            unload_radius: unload_radius.max(load_radius),
+            look_ahead: None,
+            render_distance: None,
+        }
+    }
+
+    pub fn with_look_ahead(mut self, seconds: f32) -> Self {
+        self.look_ahead = Some(seconds);
+        self
+    }
+
+    pub fn with_render_distance(mut self, distance: f32) -> Self {
+        self.render_distance = Some(distance);
+        self
+    }
+}
+
+impl Default for ChunkLoader {
+    fn default() -> Self {
+        Self::new(1, 2)
+    }
+}
+
+/// Keeps a dynamic body from falling through the map while the chunk it is in has no collider yet,
+/// without loading any chunks itself like a [`ChunkLoader`] does
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+enum FreezeOutsideChunks {
+    Sleep,      // put the body to sleep
+    Kinematic,  // turn the body kinematic
+}
+
+/// Velocity a [`FreezeOutsideChunks`] body had when it was frozen, given back once its chunk is loaded
 #[derive(Component)]
-struct ChunkLoader;
+struct FrozenVelocity {
+    linear: Vec3,
+    angular: Vec3,
+}
 
 #[derive(Component)]
 struct MinimapCamera;
@@ -177,7 +482,6 @@ struct AssetLoadingTracker(Vec<UntypedHandle>);
 
 #[derive(Resource)]
 struct AssetsCache {
-    map_scene: Handle<Scene>,
     map_gltf: Handle<bevy::gltf::Gltf>
 }
 
@@ -186,15 +490,11 @@ fn load_assets(
     mut tracker: ResMut<AssetLoadingTracker>,
     mut commands: Commands
 ) {
-    // TODO: investigate if theres a way to get this from the gltf file
-    let map_scene = server.load("earth map old.glb#Scene0");
-    tracker.0.push(map_scene.clone().into());
-
-    let map_gltf = server.load("earth map old.glb");
+    // the source tells which nodes make up the map's scene
+    let map_gltf = server.load_with_settings("earth map old.glb", |settings: &mut bevy::gltf::GltfLoaderSettings| settings.include_source = true);
     tracker.0.push(map_gltf.clone().into());
 
     commands.insert_resource(AssetsCache{
-        map_scene: map_scene,
         map_gltf: map_gltf
     })
 }
@@ -206,7 +506,7 @@ fn check_assets_ready(
 ) {
     let mut iterator = loading.0.iter().map(|h| h.id());
 
-    state.set(AssetState::Loaded);
+    state.set(AssetState::BuildingColliders);
 
     while let Some(asset) = iterator.next() {
         match server.get_load_state(asset) {
@@ -314,22 +614,37 @@ fn setup_minimap(
     minimap.order = 2;
 }
 
+/// How far above the ground under the player the minimap marker is drawn, and how far below the minimap camera it stays at most
+const MINIMAP_MARKER_HEIGHT: f32 = 20.0;
+
 fn update_minimap(
     mut gizmos: Gizmos,
     player_query: Query<&GlobalTransform, With<PlayerRigidbody>>,
+    minimap_camera_query: Query<&Transform, With<MinimapCamera>>,
+    maps: Query<&Subcollider>,
 ) {
     let global_transform = player_query.get_single().unwrap();
     let transform = global_transform.compute_transform();
+    let camera_height = minimap_camera_query.get_single().unwrap().translation.y;
+
+    // on top of whatever is under the player, so the map never hides the marker, but never above or right in front of the camera
+    let ground = maps.iter()
+        .filter_map(|map| map.height_at(transform.translation.x, transform.translation.z))
+        .reduce(f32::max)
+        .unwrap_or(transform.translation.y);
+    let marker_height = (ground + MINIMAP_MARKER_HEIGHT).min(camera_height - MINIMAP_MARKER_HEIGHT);
+    // the minimap camera has a perspective, so grow the marker with its distance to keep the size it has right under the camera
+    let marker_scale = (camera_height - marker_height) / MINIMAP_MARKER_HEIGHT;
 
     gizmos.primitive_3d(
         &Polyline3d::<3>::new(vec!(
-            Vec3::new(-0.5, 0.0, 0.5),
-            Vec3::new(0.0, 0.0, -0.5),
-            Vec3::new(0.5, 0.0, 0.5),
+            Vec3::new(-0.5, 0.0, 0.5) * marker_scale,
+            Vec3::new(0.0, 0.0, -0.5) * marker_scale,
+            Vec3::new(0.5, 0.0, 0.5) * marker_scale,
         )),
         Vec3::new(
             transform.translation.x,
-            transform.translation.y + 480.0,
+            marker_height,
             transform.translation.z
         ),
         Quat::from_rotation_y(transform.rotation.to_euler(EulerRot::YXZ).0),
@@ -338,42 +653,273 @@ fn update_minimap(
 }
 
 fn select_subcollider(
-    mut divided_colliders: Query<(Entity, &mut Subcollider, &Transform)>,
-    loader_query: Query<&Transform, With<ChunkLoader>>, 
+    mut divided_colliders: Query<(Entity, &mut Subcollider, &GlobalTransform)>,
+    loader_query: Query<(&ChunkLoader, &GlobalTransform, Option<&LinearVelocity>)>, 
+    mut activated: EventWriter<ChunkActivated>,
+    mut deactivated: EventWriter<ChunkDeactivated>,
     mut commands: Commands
 ) {
-    // nested loop hell
     for (entity, mut subcolliders, map_transform) in divided_colliders.iter_mut() {
-        for entity in subcolliders.active_colliders.clone() {
-            commands.entity(entity).despawn();
-        }
+        let subcolliders = &mut *subcolliders;
+        subcolliders.grid.transform = *map_transform;
 
-        subcolliders.active_colliders.clear();
+        let wanted = subcolliders.wanted_chunks(loader_query.iter()
+            .map(|(loader, loader_transform, velocity)| (loader, loader_transform.translation(), velocity.map(|velocity| velocity.0))));
 
-        for player_pos in loader_query.iter(){
-            for subcollider in subcolliders.colliders.clone() {
-                // i have no clue why we need to multiply by 1.725, but it works
-                let scaled_chunk_size = subcolliders.chunk_size * map_transform.scale.length() * 1.725;
-                let relative_player_pos = player_pos.translation - map_transform.translation;
+        // drop the chunks every loader has moved away from
+        subcolliders.active_colliders.retain(|chunk_pos, (_, collider_entity)| {
+            let needed = wanted.contains_key(chunk_pos);
+            if !needed {
+                commands.entity(*collider_entity).despawn_recursive();
+                deactivated.send(ChunkDeactivated { map: entity, chunk_pos: *chunk_pos, collider: *collider_entity });
+            }
+            needed
+        });
 
-                let player_pos_rounded = collider_divider::ChunkPos::from_vertex(&collider_divider::Vertex::from(relative_player_pos), &scaled_chunk_size);
-            
-                if player_pos_rounded == subcollider.0 {
-                    let entity_subcollider = commands.spawn(subcollider.1).id();
-                    subcolliders.active_colliders.push(entity_subcollider);
+        // spawn new chunks, and swap the shape of chunks a loader moved closer to or further from
+        for (chunk_pos, (lod, in_load_radius)) in wanted {
+            // the surfaces go along with the collider, so hits on it can be looked up
+            let collider = subcolliders.colliders[&chunk_pos][lod].clone();
+
+            match subcolliders.active_colliders.get_mut(&chunk_pos) {
+                // only kept alive by the unload radius
+                None if !in_load_radius => {},
+                Some((active_lod, _)) if *active_lod == lod => {},
+                Some((active_lod, collider_entity)) => {
+                    commands.entity(*collider_entity).insert(collider);
+                    *active_lod = lod;
+                },
+                None => {
+                    let entity_subcollider = commands.spawn(collider).id();
                     commands.entity(entity).add_child(entity_subcollider);
-                } 
+                    subcolliders.active_colliders.insert(chunk_pos, (lod, entity_subcollider));
+                    activated.send(ChunkActivated { map: entity, chunk_pos, collider: entity_subcollider });
+                },
+            }
+        }
+    }
+}
+
+/// Render chunks stay until every loader is this many times its render distance away,
+/// so moving back and forth along the edge doesn't keep uploading them
+const RENDER_UNLOAD_MARGIN: f32 = 1.2;
+
+fn select_render_chunks(
+    mut maps: Query<(Entity, &mut RenderChunks, &GlobalTransform)>,
+    loader_query: Query<(&ChunkLoader, &GlobalTransform)>,
+    mut assets: ResMut<Assets<Mesh>>,
+    mut commands: Commands
+) {
+    for (entity, mut render_chunks, map_transform) in maps.iter_mut() {
+        let render_chunks = &mut *render_chunks;
+        render_chunks.grid.transform = *map_transform;
+        let grid = render_chunks.grid;
+        // the map can be scaled unevenly, so a world distance covers the most local space along the least scaled axis
+        let min_scale = map_transform.compute_transform().scale.abs().min_element();
+
+        // every chunk some loader wants drawn, and whether one of them is close enough to spawn it
+        let mut wanted: HashMap<collider_divider::ChunkPos, bool> = HashMap::new();
+
+        for (loader, loader_transform) in loader_query.iter() {
+            let Some(render_distance) = loader.render_distance else { continue };
+            let unload_distance = render_distance * RENDER_UNLOAD_MARGIN;
+            let loader_pos = loader_transform.translation();
+            let local = grid.world_to_local(loader_pos);
+            let local_distance = Vec3::splat(unload_distance / min_scale);
+            let (min, max) = (grid.local_to_chunk(local - local_distance), grid.local_to_chunk(local + local_distance));
+
+            for x in min.x..=max.x {
+                for y in min.y..=max.y {
+                    for z in min.z..=max.z {
+                        let chunk_pos = collider_divider::ChunkPos { x, y, z };
+                        if !render_chunks.files.chunks.contains_key(&chunk_pos) {
+                            continue;
+                        }
+
+                        // clamping in local space finds the closest point, as the scale only stretches each axis
+                        let closest = local.clamp(grid.chunk_min(chunk_pos), grid.chunk_max(chunk_pos));
+                        let distance = grid.local_to_world(closest).distance(loader_pos);
+                        if distance <= unload_distance {
+                            *wanted.entry(chunk_pos).or_insert(false) |= distance <= render_distance;
+                        }
+                    }
+                }
+            }
+        }
+
+        // despawning the entities drops the last handle to their meshes, which frees them
+        render_chunks.active_meshes.retain(|chunk_pos, mesh_entities| {
+            let needed = wanted.contains_key(chunk_pos);
+            if !needed {
+                for mesh_entity in mesh_entities {
+                    commands.entity(*mesh_entity).despawn_recursive();
+                }
+            }
+            needed
+        });
+        // dropping a task cancels it
+        render_chunks.loading.retain(|chunk_pos, _| wanted.contains_key(chunk_pos));
+
+        for (chunk_pos, in_render_distance) in wanted {
+            if !in_render_distance || render_chunks.active_meshes.contains_key(&chunk_pos) || render_chunks.loading.contains_key(&chunk_pos) {
+                continue;
+            }
+            let task = render_chunks.load(chunk_pos);
+            render_chunks.loading.insert(chunk_pos, task);
+        }
+
+        let mut loaded = Vec::new();
+        render_chunks.loading.retain(|chunk_pos, task| match block_on(poll_once(task)) {
+            Some(meshes) => {
+                loaded.push((*chunk_pos, meshes));
+                false
+            },
+            None => true,
+        });
+
+        for (chunk_pos, meshes) in loaded {
+            let mesh_entities = meshes.into_iter()
+                .map(|(mesh, material)| {
+                    let mesh_entity = commands.spawn(PbrBundle {
+                        mesh: assets.add(mesh),
+                        material,
+                        ..default()
+                    }).id();
+                    commands.entity(entity).add_child(mesh_entity);
+                    mesh_entity
+                })
+                .collect();
+            render_chunks.active_meshes.insert(chunk_pos, mesh_entities);
+        }
+    }
+}
+
+fn freeze_unloaded_bodies(
+    maps: Query<&Subcollider>,
+    mut bodies: Query<(Entity, &FreezeOutsideChunks, &GlobalTransform, &mut RigidBody, &mut LinearVelocity, &mut AngularVelocity, Option<&FrozenVelocity>, Has<Sleeping>)>,
+    mut commands: Commands
+) {
+    for (entity, policy, transform, mut rigid_body, mut linear_velocity, mut angular_velocity, frozen, sleeping) in bodies.iter_mut() {
+        // chunks without geometry have nothing to fall through, so only the chunk the body would land on counts if it is still waiting for its collider
+        let unloaded = maps.iter().any(|map| {
+            map.chunk_below(transform.translation()).is_some_and(|chunk_pos| !map.active_colliders.contains_key(&chunk_pos))
+        });
+
+        match frozen {
+            None if unloaded && *rigid_body == RigidBody::Dynamic => {
+                commands.entity(entity).insert(FrozenVelocity { linear: linear_velocity.0, angular: angular_velocity.0 });
+
+                match policy {
+                    // sleeping bodies ignore their velocity, and changing it would wake them right back up
+                    FreezeOutsideChunks::Sleep => { commands.entity(entity).insert(Sleeping); },
+                    FreezeOutsideChunks::Kinematic => {
+                        *rigid_body = RigidBody::Kinematic;
+                        linear_velocity.0 = Vec3::ZERO;
+                        angular_velocity.0 = Vec3::ZERO;
+                    },
+                }
+            },
+            // contacts wake sleeping bodies, so put them back to sleep until their chunk is loaded
+            Some(_) if unloaded && *policy == FreezeOutsideChunks::Sleep && !sleeping => { commands.entity(entity).insert(Sleeping); },
+            Some(_) if unloaded => {},
+            Some(frozen) => {
+                linear_velocity.0 = frozen.linear;
+                angular_velocity.0 = frozen.angular;
+
+                match policy {
+                    FreezeOutsideChunks::Sleep => { commands.entity(entity).remove::<Sleeping>(); },
+                    FreezeOutsideChunks::Kinematic => *rigid_body = RigidBody::Dynamic,
+                }
+                commands.entity(entity).remove::<FrozenVelocity>();
+            },
+            None => {},
+        }
+    }
+}
+
+fn apply_terrain_edits(
+    mut edits: EventReader<TerrainEdit>,
+    mut divided_colliders: Query<&mut Subcollider>,
+    mut deactivated: EventWriter<ChunkDeactivated>,
+    mut commands: Commands
+) {
+    for TerrainEdit { map, edit } in edits.read() {
+        let Ok(mut subcolliders) = divided_colliders.get_mut(*map) else {
+            warn!("Terrain edit for {:?}, which has no subcolliders", map);
+            continue;
+        };
+        let subcolliders = &mut *subcolliders;
+
+        let changed = match subcolliders.edit_region(edit) {
+            Ok(changed) => changed,
+            Err(err) => {
+                error!("Terrain edit failed: {}", err);
+                continue;
+            }
+        };
+
+        // hot-swap the chunks that are active, `select_subcollider` spawns the rest once a loader gets close
+        for chunk_pos in changed {
+            let Some((active_lod, collider_entity)) = subcolliders.active_colliders.get_mut(&chunk_pos) else { continue };
+
+            match subcolliders.colliders.get(&chunk_pos) {
+                Some(lods) => {
+                    // the rebuilt chunk may have fewer levels of detail than before
+                    *active_lod = (*active_lod).min(lods.len() - 1);
+                    commands.entity(*collider_entity).insert(lods[*active_lod].clone());
+                },
+                None => {
+                    commands.entity(*collider_entity).despawn_recursive();
+                    deactivated.send(ChunkDeactivated { map: *map, chunk_pos, collider: *collider_entity });
+                    subcolliders.active_colliders.remove(&chunk_pos);
+                },
             }
         }
-        
+    }
+}
+
+/// A primitive of the map, placed by the transforms of its node and every node above it
+struct MapPrimitive {
+    name: String,
+    primitive: bevy::gltf::GltfPrimitive,
+    transform: GlobalTransform,
+    // false if the node or one of its parents opted out with the `split` extra
+    split: bool,
+}
+
+/// Collects the primitives of `node` and all of its children
+fn collect_map_primitives(
+    node: &bevy::gltf::GltfNode,
+    parent_transform: GlobalTransform,
+    parent_split: bool,
+    gltf_meshes: &Assets<bevy::gltf::GltfMesh>,
+    primitives: &mut Vec<MapPrimitive>
+) {
+    let transform = parent_transform.mul_transform(node.transform);
+    let split = parent_split && !map_scene::opts_out_of_split(node.extras.as_ref().map(|extras| extras.value.as_str()));
+
+    if let Some(mesh) = node.mesh.as_ref().and_then(|mesh| gltf_meshes.get(mesh)) {
+        for (index, primitive) in mesh.primitives.iter().enumerate() {
+            primitives.push(MapPrimitive {
+                name: format!("{}#{}", node.name, index),
+                primitive: primitive.clone(),
+                transform,
+                split,
+            });
+        }
+    }
+
+    for child in &node.children {
+        collect_map_primitives(child, transform, split, gltf_meshes, primitives);
     }
 }
 
 fn spawn_map(
     mut commands: Commands, 
     handles: Res<AssetsCache>, 
-    gltf: Res<Assets<bevy::gltf::Gltf>>, 
-    mut gltf_assets: ResMut<Assets<bevy::gltf::GltfMesh>>, 
+    mut gltf: ResMut<Assets<bevy::gltf::Gltf>>, 
+    gltf_nodes: Res<Assets<bevy::gltf::GltfNode>>, 
+    gltf_meshes: Res<Assets<bevy::gltf::GltfMesh>>, 
     mut assets: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>
 ) {
@@ -390,30 +936,154 @@ fn spawn_map(
         ..default()
     }, NotShadowCaster));
 
+    // the source holds a copy of the file's buffers, so it is dropped as soon as the map's scene is known
+    let roots = map_scene::scene_roots(&gltf.get_mut(&handles.map_gltf).unwrap().source.take().expect("map glTF is loaded with its source").document);
     let scene = gltf.get(&handles.map_gltf).unwrap();
+
+    // walk down from the roots of the map's scene, other scenes and nodes no scene uses aren't part of the map
+    let mut primitives = Vec::new();
+    for root in roots.into_iter().filter_map(|index| gltf_nodes.get(scene.nodes.get(index)?)) {
+        collect_map_primitives(root, GlobalTransform::IDENTITY, true, &gltf_meshes, &mut primitives);
+    }
+
+    // split primitives take their mesh out of the assets once nothing else uses it, so it isn't kept twice
+    let mut split_uses: HashMap<AssetId<Mesh>, usize> = HashMap::new();
+    for primitive in primitives.iter().filter(|primitive| primitive.split) {
+        *split_uses.entry(primitive.primitive.mesh.id()).or_default() += 1;
+    }
+    let unsplit_meshes: HashSet<AssetId<Mesh>> = primitives.iter()
+        .filter(|primitive| !primitive.split)
+        .map(|primitive| primitive.primitive.mesh.id())
+        .collect();
+
     let mut meshes = Vec::new();
+    let mut render_meshes = Vec::new();
+    let mut unsplit_primitives = Vec::new();
+
+    for MapPrimitive { name, primitive, transform, split } in primitives {
+        let material = primitive.material.clone().unwrap_or_default();
+        if !split {
+            // still collides, just as a single trimesh that is always loaded
+            let collider = assets.get(&primitive.mesh).and_then(Collider::trimesh_from_mesh);
+            if collider.is_none() {
+                error!("Map primitive {} has no collider: it has no triangles", name);
+            }
+            unsplit_primitives.push((primitive.mesh, material, transform.compute_transform(), collider));
+            continue;
+        }
+
+        let uses = split_uses.get_mut(&primitive.mesh.id()).unwrap();
+        *uses -= 1;
+        let mesh = if *uses == 0 && !unsplit_meshes.contains(&primitive.mesh.id()) {
+            assets.remove(&primitive.mesh)
+        } else {
+            assets.get(&primitive.mesh).cloned()
+        };
+        let Some(mut mesh) = mesh else {
+            error!("Map primitive {} has no mesh", name);
+            continue;
+        };
 
-    for mut primitive in gltf_assets.get_mut(&scene.meshes[0]).unwrap().primitives.clone() {
-        meshes.push(assets.get_mut(&mut primitive.mesh).unwrap().clone());
+        // every primitive is split in map space, so they all share one chunk grid
+        map_scene::bake_transform(&mut mesh, &transform.affine());
+
+        // triangles without their own `_SURFACE` are made of their material
+        let material_index = scene.materials.iter().position(|material| Some(material) == primitive.material.as_ref());
+        let mut collider_mesh = collider_divider::collider_mesh(&mesh);
+        collider_surface::insert_material_surface(&mut collider_mesh, collider_surface::material_surface(material_index));
+        meshes.push((name.clone(), collider_mesh));
+        render_meshes.push((name, mesh, material));
     }
 
+    let render_task = AsyncComputeTaskPool::get().spawn(async move {
+        let grid = MAP_SPLIT_SETTINGS.grid();
+        let cache_dir = render_cache::session_dir();
+        if let Err(err) = render_cache::clear(&cache_dir) {
+            error!("Failed to clear render cache {}: {}", cache_dir.display(), err);
+        }
+
+        let mut chunks: HashMap<collider_divider::ChunkPos, Vec<usize>> = HashMap::new();
+        let mut primitives = Vec::new();
+
+        // each mesh is dropped once its chunks are on disk
+        for (name, mesh, material) in render_meshes {
+            let chunk_meshes = match collider_divider::try_split_render_mesh(&mesh, &grid) {
+                Ok(chunk_meshes) => chunk_meshes,
+                Err(err) => {
+                    error!("Map primitive {} won't be drawn: {}", name, err);
+                    continue;
+                },
+            };
+
+            let primitive = primitives.len();
+            for (chunk_pos, chunk_mesh) in chunk_meshes {
+                match render_cache::store(&cache_dir, primitive, chunk_pos, &chunk_mesh) {
+                    Ok(_) => chunks.entry(chunk_pos).or_default().push(primitive),
+                    Err(err) => error!("Map primitive {} won't be drawn in chunk {:?}: {}", name, chunk_pos, err),
+                }
+            }
+            primitives.push((collider_divider::empty_render_mesh(&mesh), material));
+        }
+
+        RenderChunkFiles { dir: cache_dir, chunks, primitives: Arc::new(primitives) }
+    });
+
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        let task_pool = AsyncComputeTaskPool::get();
+
+        // split every primitive at once
+        let meshlets = task_pool.scope(|scope| {
+            for (name, mesh) in &meshes {
+                scope.spawn(async move {
+                    collider_cache::load_or_split(mesh, &MAP_SPLIT_SETTINGS, std::path::Path::new(COLLIDER_CACHE_DIR))
+                        .unwrap_or_else(|err| {
+                            error!("Map primitive {} has no collider: {}", name, err);
+                            Vec::new()
+                        })
+                });
+            }
+        })
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+        // several primitives can have geometry in the same chunk, and sparse chunks share one collider,
+        // then build the colliders in parallel as well
+        let meshlets = collider_divider::merge_meshlets(meshlets);
+        let (tree, meshlets) = collider_divider::build_chunk_tree(meshlets, &MAP_CHUNK_TREE, &MAP_SPLIT_SETTINGS);
+        let colliders = collider_divider::to_subcolliders_parallel(&meshlets, task_pool);
+        Subcollider::new(meshlets, colliders, MAP_SPLIT_SETTINGS.grid(), tree)
+    });
+
     commands.spawn((
-        SceneBundle {
+        SpatialBundle {
             transform: Transform::from_xyz(0.0, -800.0, 0.0).with_scale(Vec3::splat(20.0)),
-            scene: handles.map_scene.clone(),
             ..default()
         },
-        Subcollider::new(
-            meshes.into_iter().fold(Vec::new(), |mut accumulator, mesh| {
-                accumulator.extend(collider_divider::split_subcolliders(&mesh, CHUNK_SIZE));
-                accumulator
-            }),
-            10.0,
-        ),
+        SubcolliderTask(task),
+        RenderChunkTask(render_task),
         RigidBody::Static,
         CollisionMargin(0.4),
     ))
     .with_children(|children| {
+        // the scene keeps the map's lights and cameras, its meshes are drawn by chunk instead
+        if let Some(scene) = scene.default_scene.clone().or_else(|| scene.scenes.first().cloned()) {
+            children.spawn((SceneBundle { scene, ..default() }, MapSceneNodes));
+        }
+
+        // nodes that opted out of the split are drawn whole, with one collider each
+        for (mesh, material, transform, collider) in unsplit_primitives {
+            let mut unsplit = children.spawn(PbrBundle {
+                mesh,
+                material,
+                transform,
+                ..default()
+            });
+            if let Some(collider) = collider {
+                unsplit.insert(collider);
+            }
+        }
+
         children.spawn(SpotLightBundle {
             spot_light: SpotLight {
                 radius: 10.0,
@@ -460,6 +1130,81 @@ fn spawn_map(
     });
 }
 
+/// Takes the meshes off the map's scene as soon as it is spawned, before they are drawn on top of the render chunks
+fn strip_map_scene_meshes(
+    mut ready: EventReader<SceneInstanceReady>,
+    map_scenes: Query<(), With<MapSceneNodes>>,
+    children: Query<&Children>,
+    meshes: Query<(), With<Handle<Mesh>>>,
+    mut commands: Commands
+) {
+    for SceneInstanceReady { parent } in ready.read() {
+        if !map_scenes.contains(*parent) {
+            continue;
+        }
+        for node in children.iter_descendants(*parent).filter(|node| meshes.contains(*node)) {
+            commands.entity(node).remove::<(Handle<Mesh>, Handle<StandardMaterial>)>();
+        }
+    }
+}
+
+fn poll_subcollider_tasks(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut SubcolliderTask)>,
+    mut state: ResMut<NextState<AssetState>>
+) {
+    let mut pending = 0;
+
+    for (entity, mut task) in tasks.iter_mut() {
+        match block_on(poll_once(&mut task.0)) {
+            Some(subcolliders) => {
+                commands.entity(entity)
+                    .remove::<SubcolliderTask>()
+                    .insert(subcolliders);
+            },
+            None => pending += 1,
+        }
+    }
+
+    if pending == 0 {
+        state.set(AssetState::Loaded);
+    }
+}
+
+fn poll_render_chunk_tasks(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut RenderChunkTask)>
+) {
+    for (entity, mut task) in tasks.iter_mut() {
+        if let Some(files) = block_on(poll_once(&mut task.0)) {
+            commands.entity(entity)
+                .remove::<RenderChunkTask>()
+                .insert(RenderChunks::new(files, MAP_SPLIT_SETTINGS.grid()));
+        }
+    }
+}
+
+/// Deletes the render chunk files when the game closes, as nothing reads them after this process
+fn remove_render_cache(mut exit: EventReader<AppExit>) {
+    if exit.read().next().is_none() {
+        return;
+    }
+
+    let cache_dir = render_cache::session_dir();
+    if let Err(err) = render_cache::remove(&cache_dir) {
+        error!("Failed to remove render cache {}: {}", cache_dir.display(), err);
+    }
+}
+
+// keep the player from falling through the world until its colliders exist
+fn pause_physics(mut time: ResMut<Time<Physics>>) {
+    time.pause();
+}
+
+fn resume_physics(mut time: ResMut<Time<Physics>>) {
+    time.unpause();
+}
+
 fn confine_mouse(mut primary_window: Query<&mut Window, With<PrimaryWindow>>) {
     let mut window = primary_window.get_single_mut().unwrap();
     window.cursor.grab_mode = CursorGrabMode::Confined;
@@ -498,6 +1243,7 @@ fn set_window_icon(
 #[derive(States, Debug, Clone, PartialEq, Eq, Hash)]
 enum AssetState {
     Loading,
+    BuildingColliders,
     Loaded
 }
 
@@ -517,7 +1263,8 @@ fn main() {
                     ..default()
                 }),
                 ..default()
-            }),
+            })
+            .set(GltfPlugin::default().add_custom_vertex_attribute("SURFACE", collider_surface::ATTRIBUTE_SURFACE)),
             LogDiagnosticsPlugin::default()
         ))
         //.add_plugins(WorldInspectorPlugin::new())
@@ -532,18 +1279,182 @@ fn main() {
         // TODO: save player preferences
         .init_state::<GameState>()
         .init_resource::<AssetLoadingTracker>()
-        .insert_resource(Keybinds { pause: KeyCode::Escape });
+        .insert_resource(Keybinds { pause: KeyCode::Escape })
+        .add_event::<ChunkActivated>()
+        .add_event::<ChunkDeactivated>()
+        .add_event::<TerrainEdit>();
 
     app.add_systems(PreStartup, load_assets)
         .add_systems(PreStartup, setup_camera)
         .add_systems(PreStartup, set_window_icon)
         .add_systems(Startup, setup_minimap)
         .add_systems(Startup, confine_mouse)
-        .add_systems(OnEnter(AssetState::Loaded), spawn_map)
+        .add_systems(OnEnter(AssetState::Loading), pause_physics)
+        .add_systems(OnEnter(AssetState::BuildingColliders), spawn_map)
+        .add_systems(OnEnter(AssetState::Loaded), resume_physics)
         .add_systems(Update, check_assets_ready.run_if(in_state(AssetState::Loading)))
-        .add_systems(PreUpdate, select_subcollider.run_if(in_state(AssetState::Loaded)))
+        .add_systems(Update, poll_subcollider_tasks.run_if(in_state(AssetState::BuildingColliders)))
+        .add_systems(PreUpdate, (apply_terrain_edits, select_subcollider).chain().run_if(in_state(AssetState::Loaded)))
+        .add_systems(Update, poll_render_chunk_tasks)
+        .add_systems(SpawnScene, strip_map_scene_meshes.after(scene_spawner_system))
+        .add_systems(Update, select_render_chunks.run_if(in_state(AssetState::Loaded)))
+        .add_systems(Update, freeze_unloaded_bodies.run_if(in_state(AssetState::Loaded)))
         .add_systems(Update, move_camera.run_if(in_state(AssetState::Loaded)))
         .add_systems(Update, update_minimap.run_if(in_state(AssetState::Loaded)))
         .add_systems(PostUpdate, resize_minimap.run_if(in_state(AssetState::Loaded)))
+        .add_systems(Last, remove_render_cache)
         .run();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use voyage_abeon::collider_divider::{ChunkPos, ChunkTreeSettings, LodLevel, SplitSettings};
+
+    const LODS: &[LodLevel] = &[
+        LodLevel { target_ratio: 0.25, max_error: 0.1 },
+        LodLevel { target_ratio: 0.25, max_error: 0.1 },
+    ];
+    const SETTINGS: SplitSettings = SplitSettings::new(4.0).with_lods(LODS);
+
+    /// A flat map from -20 to 20 along x and z, made of unit quads, split into 4 unit chunks that all have two coarser levels
+    fn flat_map() -> Subcollider {
+        let positions: Vec<[f32; 3]> = (-20..=20).flat_map(|z| (-20..=20).map(move |x| [x as f32, 0.0, z as f32])).collect();
+        let indices: Vec<u32> = (0..40).flat_map(|z| (0..40).flat_map(move |x| {
+            let corner = z * 41 + x;
+            [corner, corner + 41, corner + 1, corner + 1, corner + 41, corner + 42]
+        })).collect();
+        let mesh = Mesh::new(bevy::render::mesh::PrimitiveTopology::TriangleList, RenderAssetUsages::default())
+            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+            .with_inserted_indices(bevy::render::mesh::Indices::U32(indices));
+
+        let meshlets = collider_divider::try_split_meshlets(&mesh, &SETTINGS).unwrap();
+        // single chunk leaves, so rings are counted in grid chunks
+        let (tree, meshlets) = collider_divider::build_chunk_tree(meshlets, &ChunkTreeSettings { max_triangles: 0, max_level: 0 }, &SETTINGS);
+        let colliders = collider_divider::to_subcolliders(&meshlets);
+        Subcollider::new(meshlets, colliders, SETTINGS.grid(), tree)
+    }
+
+    fn chunk(x: i32, z: i32) -> ChunkPos {
+        ChunkPos { x, y: 0, z }
+    }
+
+    /// World position in the middle of a chunk
+    fn center(x: i32, z: i32) -> Vec3 {
+        Vec3::new(x as f32 * 4.0 + 2.0, 1.0, z as f32 * 4.0 + 2.0)
+    }
+
+    #[test]
+    fn loaders_want_rings_of_coarser_chunks() {
+        let map = flat_map();
+        assert!(map.colliders.values().all(|lods| lods.len() == 3));
+
+        let loader = ChunkLoader::new(1, 2).with_look_ahead(1.0);
+        let wanted = map.wanted_chunks([(&loader, center(0, 0), None)]);
+
+        // the load radius is loaded, the unload radius only kept, and each ring uses the next coarser level
+        assert_eq!(wanted.len(), 25);
+        assert_eq!(wanted[&chunk(0, 0)], (0, true));
+        assert_eq!(wanted[&chunk(1, -1)], (1, true));
+        assert_eq!(wanted[&chunk(-2, 1)], (2, false));
+        assert!(!wanted.contains_key(&chunk(3, 0)));
+
+        // heading along x loads around where the loader will be a second from now, and a closer loader's level wins
+        let wanted = map.wanted_chunks([(&loader, center(0, 0), Some(Vec3::X * 8.0))]);
+        assert_eq!(wanted[&chunk(3, 0)], (1, true));
+        assert_eq!(wanted[&chunk(2, 0)], (0, true));
+        assert_eq!(wanted[&chunk(4, 0)], (2, false));
+        assert_eq!(wanted[&chunk(-2, 0)], (2, false));
+    }
+
+    /// Sorted so chunk lists can be compared
+    fn sorted(chunks: impl IntoIterator<Item = ChunkPos>) -> Vec<ChunkPos> {
+        chunks.into_iter().sorted_by_key(|chunk_pos| (chunk_pos.x, chunk_pos.z)).collect()
+    }
+
+    /// Moves `loader` to `position` and updates, returning the chunks that were activated and deactivated
+    fn step(app: &mut App, loader: Entity, position: Vec3) -> (Vec<ChunkPos>, Vec<ChunkPos>) {
+        app.world_mut().entity_mut(loader).insert(GlobalTransform::from_translation(position));
+        app.update();
+
+        let activated = sorted(app.world_mut().resource_mut::<Events<ChunkActivated>>().drain().map(|event| event.chunk_pos));
+        let deactivated = sorted(app.world_mut().resource_mut::<Events<ChunkDeactivated>>().drain().map(|event| event.chunk_pos));
+
+        // one collider entity per active chunk, no matter how many loaders want it
+        let active = app.world_mut().query::<&Subcollider>().single(app.world()).active_colliders.len();
+        assert_eq!(app.world_mut().query::<&Collider>().iter(app.world()).count(), active);
+
+        (activated, deactivated)
+    }
+
+    #[test]
+    fn chunks_stream_with_hysteresis_and_one_event_per_change() {
+        let mut app = App::new();
+        app.add_event::<ChunkActivated>()
+            .add_event::<ChunkDeactivated>()
+            .add_systems(Update, select_subcollider);
+
+        let map = app.world_mut().spawn((flat_map(), GlobalTransform::IDENTITY)).id();
+        let loader = app.world_mut().spawn((ChunkLoader::new(1, 2), GlobalTransform::IDENTITY)).id();
+        let active = |app: &App| sorted(app.world().get::<Subcollider>(map).unwrap().active_colliders.keys().copied());
+        let column = |x: i32| vec![chunk(x, -1), chunk(x, 0), chunk(x, 1)];
+
+        assert_eq!(step(&mut app, loader, center(0, 0)), ([column(-1), column(0), column(1)].concat(), vec![]));
+
+        // stepping into the next chunk only loads the column that came into the load radius
+        assert_eq!(step(&mut app, loader, center(1, 0)), (column(2), vec![]));
+
+        // moving back and forth across the border keeps every chunk instead of swapping them each frame
+        for _ in 0..4 {
+            for position in [center(0, 0), Vec3::new(3.99, 1.0, 2.0), Vec3::new(4.0, 1.0, 2.0), center(1, 0)] {
+                assert_eq!(step(&mut app, loader, position), (vec![], vec![]));
+            }
+        }
+        assert_eq!(active(&app), [column(-1), column(0), column(1), column(2)].concat());
+
+        // a second loader in the same place shares the chunks
+        let other_loader = app.world_mut().spawn((ChunkLoader::new(1, 2), GlobalTransform::from_translation(center(1, 0)))).id();
+        assert_eq!(step(&mut app, loader, center(1, 0)), (vec![], vec![]));
+
+        // once every loader is out of their unload radius, each chunk is dropped exactly once
+        app.world_mut().despawn(other_loader);
+        assert_eq!(step(&mut app, loader, center(4, 0)), ([column(3), column(4), column(5)].concat(), [column(-1), column(0), column(1)].concat()));
+        assert_eq!(active(&app), [column(2), column(3), column(4), column(5)].concat());
+    }
+
+    #[test]
+    fn bodies_freeze_until_the_chunk_below_them_loads() {
+        let mut app = App::new();
+        app.add_event::<ChunkActivated>()
+            .add_event::<ChunkDeactivated>()
+            .add_systems(Update, (select_subcollider, freeze_unloaded_bodies).chain());
+
+        app.world_mut().spawn((flat_map(), GlobalTransform::IDENTITY));
+        let body = |policy| (policy, RigidBody::Dynamic, LinearVelocity(Vec3::new(1.0, -2.0, 0.0)), AngularVelocity(Vec3::Y), GlobalTransform::from_translation(center(0, 0)));
+        let kinematic = app.world_mut().spawn(body(FreezeOutsideChunks::Kinematic)).id();
+        let sleeping = app.world_mut().spawn(body(FreezeOutsideChunks::Sleep)).id();
+
+        app.update();
+        assert_eq!(*app.world().get::<RigidBody>(kinematic).unwrap(), RigidBody::Kinematic);
+        assert_eq!(app.world().get::<LinearVelocity>(kinematic).unwrap().0, Vec3::ZERO);
+        assert!(app.world().get::<Sleeping>(sleeping).is_some());
+        assert_eq!(*app.world().get::<RigidBody>(sleeping).unwrap(), RigidBody::Dynamic);
+
+        // a contact wakes the sleeping body while its chunk is still unloaded, so it is put back to sleep
+        app.world_mut().entity_mut(sleeping).remove::<Sleeping>();
+        app.update();
+        assert!(app.world().get::<Sleeping>(sleeping).is_some());
+        assert!(app.world().get::<FrozenVelocity>(sleeping).is_some());
+
+        // once the chunk below them is active, both get their velocity back and move on
+        app.world_mut().spawn((ChunkLoader::new(0, 0), GlobalTransform::from_translation(center(0, 0))));
+        app.update();
+        for body in [kinematic, sleeping] {
+            assert_eq!(*app.world().get::<RigidBody>(body).unwrap(), RigidBody::Dynamic);
+            assert_eq!(app.world().get::<LinearVelocity>(body).unwrap().0, Vec3::new(1.0, -2.0, 0.0));
+            assert_eq!(app.world().get::<AngularVelocity>(body).unwrap().0, Vec3::Y);
+            assert!(app.world().get::<FrozenVelocity>(body).is_none());
+            assert!(app.world().get::<Sleeping>(body).is_none());
+        }
+    }
+}
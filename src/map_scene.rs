@@ -0,0 +1,120 @@
+use bevy::math::{Affine3A, Mat3A, Vec3A};
+use bevy::prelude::{Mesh, Vec3};
+use bevy::render::mesh::{Indices, PrimitiveTopology, VertexAttributeValues};
+
+/// glTF extra a node sets to `false` to keep itself and all of its children out of the chunk split,
+/// e.g. `{"split": false}` on a dock or building that should be drawn as a whole.
+/// Such nodes still collide, with one trimesh collider per primitive that is never unloaded.
+pub const SPLIT_EXTRA: &str = "split";
+
+/// Whether a node with the glTF `extras` JSON opted out of the chunk split
+pub fn opts_out_of_split(extras: Option<&str>) -> bool {
+    extras
+        .and_then(|extras| serde_json::from_str::<serde_json::Value>(extras).ok())
+        .and_then(|extras| extras.get(SPLIT_EXTRA)?.as_bool())
+        == Some(false)
+}
+
+/// Indices of the root nodes of the scene the map is made of: the file's default scene, or its first one if it names none.
+/// Nodes of other scenes and nodes no scene uses aren't part of the map.
+pub fn scene_roots(document: &gltf::Document) -> Vec<usize> {
+    document.default_scene()
+        .or_else(|| document.scenes().next())
+        .map(|scene| scene.nodes().map(|node| node.index()).collect())
+        .unwrap_or_default()
+}
+
+/// Moves the positions, normals and tangents of `mesh` into the space `transform` maps to,
+/// so primitives of different nodes can be split into the same chunk grid.
+/// Mirroring transforms also flip the winding of triangle lists, so their front faces stay on the outside.
+pub fn bake_transform(mesh: &mut Mesh, transform: &Affine3A) {
+    let normal_matrix = transform.matrix3.inverse().transpose();
+    let mirrored = transform.matrix3.determinant() < 0.0;
+
+    if let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION) {
+        for position in positions {
+            *position = transform.transform_point3(Vec3::from(*position)).to_array();
+        }
+    }
+    if let Some(VertexAttributeValues::Float32x3(normals)) = mesh.attribute_mut(Mesh::ATTRIBUTE_NORMAL) {
+        for normal in normals {
+            *normal = transform_direction(&normal_matrix, Vec3::from(*normal)).to_array();
+        }
+    }
+    if let Some(VertexAttributeValues::Float32x4(tangents)) = mesh.attribute_mut(Mesh::ATTRIBUTE_TANGENT) {
+        for [x, y, z, handedness] in tangents {
+            [*x, *y, *z] = transform_direction(&transform.matrix3, Vec3::new(*x, *y, *z)).to_array();
+            if mirrored {
+                *handedness = -*handedness;
+            }
+        }
+    }
+
+    if mirrored && mesh.primitive_topology() == PrimitiveTopology::TriangleList {
+        if mesh.indices().is_none() {
+            let vertex_count = mesh.count_vertices() as u32;
+            mesh.insert_indices(Indices::U32((0..vertex_count).collect()));
+        }
+        match mesh.indices_mut() {
+            Some(Indices::U16(indices)) => indices.chunks_exact_mut(3).for_each(|triangle| triangle.swap(1, 2)),
+            Some(Indices::U32(indices)) => indices.chunks_exact_mut(3).for_each(|triangle| triangle.swap(1, 2)),
+            None => {},
+        }
+    }
+}
+
+fn transform_direction(matrix: &Mat3A, direction: Vec3) -> Vec3 {
+    Vec3::from(*matrix * Vec3A::from(direction)).normalize_or_zero()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::prelude::{Quat, Transform};
+    use bevy::render::render_asset::RenderAssetUsages;
+
+    #[test]
+    fn split_extra_opts_out() {
+        assert!(opts_out_of_split(Some(r#"{"split": false}"#)));
+        assert!(!opts_out_of_split(Some(r#"{"split": true, "note": "dock"}"#)));
+        assert!(!opts_out_of_split(Some(r#"{"split": "no"}"#)));
+        assert!(!opts_out_of_split(Some("not json")));
+        assert!(!opts_out_of_split(None));
+    }
+
+    #[test]
+    fn only_the_default_scene_is_the_map() {
+        let json = |default_scene: &str| format!(r#"{{
+            "asset": {{"version": "2.0"}},
+            {default_scene}
+            "scenes": [{{"nodes": [0]}}, {{"nodes": [1, 3]}}],
+            "nodes": [{{"name": "other scene"}}, {{"children": [2]}}, {{"name": "child"}}, {{"name": "root"}}, {{"name": "orphan"}}]
+        }}"#);
+
+        let document = gltf::Gltf::from_slice(json(r#""scene": 1,"#).as_bytes()).unwrap().document;
+        assert_eq!(scene_roots(&document), vec![1, 3]);
+        let document = gltf::Gltf::from_slice(json("").as_bytes()).unwrap().document;
+        assert_eq!(scene_roots(&document), vec![0]);
+    }
+
+    #[test]
+    fn mirrored_transform_keeps_front_faces() {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default());
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]]);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0, 1.0, 0.0]; 3]);
+
+        let transform = Transform::from_xyz(10.0, 2.0, -3.0)
+            .with_rotation(Quat::from_rotation_y(0.7))
+            .with_scale(Vec3::new(-2.0, 1.0, 3.0));
+        bake_transform(&mut mesh, &transform.compute_affine());
+
+        let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else { panic!("positions were removed") };
+        let Some(VertexAttributeValues::Float32x3(normals)) = mesh.attribute(Mesh::ATTRIBUTE_NORMAL) else { panic!("normals were removed") };
+        let triangle: Vec<Vec3> = mesh.indices().unwrap().iter().map(|i| Vec3::from(positions[i])).collect();
+
+        // the winding still agrees with the vertex normals
+        let face_normal = (triangle[1] - triangle[0]).cross(triangle[2] - triangle[0]).normalize();
+        assert!(face_normal.dot(Vec3::from(normals[0])) > 0.999);
+        assert!(triangle[0].distance(transform.translation) < 1e-5);
+    }
+}
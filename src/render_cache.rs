@@ -0,0 +1,237 @@
+use bevy::prelude::Mesh;
+use bevy::render::mesh::{Indices, VertexAttributeValues};
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use crate::collider_cache::Fnv1a;
+use crate::collider_divider::ChunkPos;
+
+const MAGIC: &[u8; 4] = b"VARC";
+// bump this whenever the file layout changes
+const VERSION: u32 = 1;
+
+/// Where this process keeps the map's split render meshes while it runs. Every process gets a directory of its own,
+/// so a second instance clearing its chunk files at launch doesn't delete the ones the first is still reading.
+pub fn session_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("voyage-abeon-render-{}", std::process::id()))
+}
+
+/// Where the part of the map primitive numbered `primitive` that falls into `chunk_pos` is stored
+pub fn chunk_path(cache_dir: &Path, primitive: usize, chunk_pos: ChunkPos) -> PathBuf {
+    cache_dir.join(format!("{}_{}_{}_{}.bin", primitive, chunk_pos.x, chunk_pos.y, chunk_pos.z))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Stores the values of every attribute of `mesh` in the order [`Mesh::attributes`] lists them, then its indices.
+/// Attribute ids and formats aren't stored, [`decode`] takes them from an empty mesh with the same attributes.
+/// Values are stored in native byte order, as the files only live for one session.
+pub fn encode(mesh: &Mesh) -> Vec<u8> {
+    let mut bytes = Vec::new();
+
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(mesh.count_vertices() as u32).to_le_bytes());
+    bytes.extend_from_slice(&(mesh.attributes().count() as u32).to_le_bytes());
+
+    for (_, values) in mesh.attributes() {
+        let values = values.get_bytes();
+        bytes.extend_from_slice(&(values.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(values);
+    }
+
+    let indices: Vec<u32> = mesh.indices().map_or_else(Vec::new, |indices| indices.iter().map(|i| i as u32).collect());
+    bytes.extend_from_slice(&(indices.len() as u32).to_le_bytes());
+    for index in indices {
+        bytes.extend_from_slice(&index.to_le_bytes());
+    }
+
+    let mut hasher = Fnv1a::new();
+    hasher.write(&bytes);
+    bytes.extend_from_slice(&hasher.0.to_le_bytes());
+
+    bytes
+}
+
+/// Reads values in the format of `template` from the bytes [`VertexAttributeValues::get_bytes`] returned
+fn values_like(template: &VertexAttributeValues, bytes: &[u8]) -> VertexAttributeValues {
+    macro_rules! values_like {
+        ($($format:ident),*) => {
+            match template {
+                $(VertexAttributeValues::$format(_) => VertexAttributeValues::$format(bytemuck::pod_collect_to_vec(bytes)),)*
+            }
+        };
+    }
+
+    values_like!(
+        Float32, Sint32, Uint32, Float32x2, Sint32x2, Uint32x2, Float32x3, Sint32x3, Uint32x3, Float32x4, Sint32x4, Uint32x4,
+        Sint16x2, Snorm16x2, Uint16x2, Unorm16x2, Sint16x4, Snorm16x4, Uint16x4, Unorm16x4,
+        Sint8x2, Snorm8x2, Uint8x2, Unorm8x2, Sint8x4, Snorm8x4, Uint8x4, Unorm8x4
+    )
+}
+
+/// Bounds-checked reader, so a corrupt length can't make us allocate or read garbage
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(invalid("unexpected end of render cache"));
+        }
+
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+/// Reads a chunk mesh written by [`encode`]. `template` is an empty mesh with the attributes and settings of the
+/// mesh that was split, see [`collider_divider::empty_render_mesh`](crate::collider_divider::empty_render_mesh).
+pub fn decode(template: &Mesh, bytes: &[u8]) -> io::Result<Mesh> {
+    if bytes.len() < 8 {
+        return Err(invalid("render cache too short"));
+    }
+
+    let (payload, checksum) = bytes.split_at(bytes.len() - 8);
+    let mut hasher = Fnv1a::new();
+    hasher.write(payload);
+    if hasher.0.to_le_bytes() != checksum {
+        return Err(invalid("render cache checksum mismatch"));
+    }
+
+    let mut reader = Reader { bytes: payload };
+
+    if reader.take(4)? != MAGIC {
+        return Err(invalid("not a render cache"));
+    }
+    if reader.u32()? != VERSION {
+        return Err(invalid("render cache version mismatch"));
+    }
+
+    let vertex_count = reader.u32()? as usize;
+    if reader.u32()? as usize != template.attributes().count() {
+        return Err(invalid("render cache was written from a different mesh"));
+    }
+
+    let mut mesh = template.clone();
+    for (_, values) in mesh.attributes_mut() {
+        let len = reader.u32()? as usize;
+        let bytes = reader.take(len)?;
+        let read = values_like(values, bytes);
+        // a length that isn't a whole number of values would have been silently cut short
+        if read.len() != vertex_count || read.get_bytes().len() != len {
+            return Err(invalid("render cache attribute length mismatch"));
+        }
+        *values = read;
+    }
+
+    let index_count = reader.u32()? as usize;
+    if index_count.saturating_mul(4) != reader.bytes.len() {
+        return Err(invalid("render cache index count out of range"));
+    }
+    let indices = (0..index_count).map(|_| reader.u32()).collect::<io::Result<Vec<u32>>>()?;
+    if indices.iter().any(|i| *i as usize >= vertex_count) {
+        return Err(invalid("render cache index out of range"));
+    }
+    mesh.insert_indices(Indices::U32(indices));
+
+    Ok(mesh)
+}
+
+/// Writes `mesh` to the file for `primitive` in `chunk_pos`, returning its path
+pub fn store(cache_dir: &Path, primitive: usize, chunk_pos: ChunkPos, mesh: &Mesh) -> io::Result<PathBuf> {
+    let path = chunk_path(cache_dir, primitive, chunk_pos);
+    fs::write(&path, encode(mesh))?;
+    Ok(path)
+}
+
+/// Deletes `cache_dir` and every chunk file in it, if it exists
+pub fn remove(cache_dir: &Path) -> io::Result<()> {
+    match fs::remove_dir_all(cache_dir) {
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Empties `cache_dir`, since chunk files are only valid for the session that split them.
+/// A process id can be reused, so [`session_dir`] may still hold the files of an earlier process.
+pub fn clear(cache_dir: &Path) -> io::Result<()> {
+    remove(cache_dir)?;
+    fs::create_dir_all(cache_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::mesh::PrimitiveTopology;
+    use bevy::render::render_asset::RenderAssetUsages;
+    use crate::collider_divider::empty_render_mesh;
+    use crate::collider_surface;
+
+    fn mesh() -> Mesh {
+        Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default())
+            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0, 1.0]])
+            .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [1.0, 1.0]])
+            .with_inserted_attribute(collider_surface::ATTRIBUTE_SURFACE, vec![1u32, 2, 3, 4])
+            .with_inserted_indices(Indices::U16(vec![0, 2, 1, 1, 2, 3]))
+    }
+
+    #[test]
+    fn round_trip() {
+        let mesh = mesh();
+        let decoded = decode(&empty_render_mesh(&mesh), &encode(&mesh)).unwrap();
+
+        for (id, values) in mesh.attributes() {
+            assert_eq!(decoded.attribute(id).unwrap().get_bytes(), values.get_bytes());
+        }
+        assert_eq!(decoded.attributes().count(), mesh.attributes().count());
+        assert_eq!(decoded.indices().unwrap().iter().collect::<Vec<_>>(), vec![0, 2, 1, 1, 2, 3]);
+    }
+
+    #[test]
+    fn rejects_corrupt_input() {
+        let mesh = mesh();
+        let template = empty_render_mesh(&mesh);
+        let bytes = encode(&mesh);
+
+        for len in 0..bytes.len() {
+            assert!(decode(&template, &bytes[..len]).is_err(), "accepted {len} of {} bytes", bytes.len());
+        }
+
+        let mut flipped = bytes.clone();
+        flipped[20] ^= 1;
+        assert!(decode(&template, &flipped).is_err());
+
+        // a template with other attributes can't read the file
+        let mut other = template.clone();
+        other.remove_attribute(Mesh::ATTRIBUTE_UV_0);
+        assert!(decode(&other, &bytes).is_err());
+    }
+
+    #[test]
+    fn session_files_are_cleared_and_removed() {
+        let dir = session_dir().join("session_files_are_cleared_and_removed");
+        let chunk_pos = ChunkPos { x: -1, y: 0, z: 2 };
+
+        clear(&dir).unwrap();
+        let path = store(&dir, 0, chunk_pos, &mesh()).unwrap();
+        assert!(path.starts_with(std::env::temp_dir()) && path.exists());
+
+        clear(&dir).unwrap();
+        assert!(dir.exists() && !path.exists());
+
+        remove(&dir).unwrap();
+        assert!(!dir.exists());
+        // already gone is fine too
+        remove(&dir).unwrap();
+        remove(&session_dir()).unwrap();
+    }
+}